@@ -21,6 +21,27 @@ enum class ArgType {
   FuncRef,
 };
 
+/// Outcome of a call into the library. Anything but `Ok` leaves the out-parameters untouched
+/// and records a message for `last_error_message`.
+enum class WasmStatus {
+  Ok,
+  /// A pointer is null or a string isn't valid UTF-8 or base64.
+  InvalidArgument,
+  RuntimeNotFound,
+  ModuleNotFound,
+  FunctionNotFound,
+  /// The arguments don't match the function signature.
+  SignatureMismatch,
+  /// The module couldn't be compiled or linked.
+  RegistrationFailed,
+  /// Instantiating or running the function failed.
+  ExecutionFailed,
+  /// The library panicked, this is a bug.
+  Panic,
+};
+
+/// Example modules bundled with the library. Registered modules are exported back from their
+/// own bytes, these are only a source of data to register.
 enum class StaticModuleList {
   WasmDiv,
   WasmSum,
@@ -33,25 +54,133 @@ struct WasmArg {
 
 struct WasmFunction {
   const char *name;
-  WasmArg args[2];
+  /// `args_len` arguments, may be null when there are none.
+  const WasmArg *args;
+  uintptr_t args_len;
+};
+
+union WasmValueData {
+  int32_t i32;
+  int64_t i64;
+  float f32;
+  double f64;
+  /// Little-endian bytes.
+  uint8_t v128[16];
+  /// `ExternRef` host handles and `FuncRef` export names as text, null for null references.
+  /// Owned by the [`WasmResults`] it is part of.
+  char *reference;
+};
+
+struct WasmValue {
+  ArgType value_type;
+  WasmValueData data;
+};
+
+/// Values returned by a function, empty for functions that return nothing. Must be released
+/// with `free_wasm_results`.
+struct WasmResults {
+  WasmValue *values;
+  uintptr_t len;
+};
+
+/// Owned nul-terminated strings. Must be released with `free_wasm_strings`.
+struct WasmStrings {
+  char **values;
+  uintptr_t len;
 };
 
 extern "C" {
 
-uint64_t initialize_runtime();
+/// # Safety
+/// `runtime_id` must be null or valid for writes.
+WasmStatus initialize_runtime(uint64_t *runtime_id);
 
-char *get_static_module_data(StaticModuleList module);
+/// Removes the runtime and every module registered with it.
+WasmStatus destroy_runtime(uint64_t runtime_id);
 
-const char *get_runtime_module_base64_data(uint64_t runtime_id, const char *module_name);
+/// Unregisters every module of the runtime, keeping its id.
+WasmStatus reset_runtime(uint64_t runtime_id);
 
-const char *register_module(uint64_t runtime_id,
-                            const char *module_name,
-                            const char *module_data_base_64);
+/// Creates a runtime with the modules of `runtime_id` registered. Compiled modules are shared,
+/// registering or removing modules afterwards only affects one of the two.
+///
+/// # Safety
+/// `clone_id` must be null or valid for writes.
+WasmStatus clone_runtime(uint64_t runtime_id, uint64_t *clone_id);
 
+/// Writes the ids of up to `capacity` runtimes to `runtime_ids` and the number of runtimes to
+/// `count`, which may be larger than `capacity`. Ids are in creation order.
+///
+/// # Safety
+/// `runtime_ids` must be valid for `capacity` writes, or null when `capacity` is 0. `count`
+/// must be null or valid for writes.
+WasmStatus list_runtimes(uint64_t *runtime_ids, uintptr_t capacity, uintptr_t *count);
+
+/// Writes the names of the modules registered with the runtime, sorted, to `names`.
+///
+/// # Safety
+/// `names` must be null or valid for writes. The names written to it must be released with
+/// [`free_wasm_strings`].
+WasmStatus list_modules(uint64_t runtime_id, WasmStrings *names);
+
+/// # Safety
+/// `strings` must come from this library and not have been freed yet.
+void free_wasm_strings(WasmStrings strings);
+
+/// # Safety
+/// `data` must be null or valid for writes. The string written to it must be released with
+/// [`free_ffi_string`].
+WasmStatus get_static_module_data(StaticModuleList module, char **data);
+
+/// # Safety
+/// `module_name` must be a valid nul-terminated string, `data` must be null or valid for
+/// writes. The string written to it must be released with [`free_ffi_string`].
+WasmStatus get_runtime_module_base64_data(uint64_t runtime_id,
+                                          const char *module_name,
+                                          char **data);
+
+/// # Safety
+/// `module_name` and `module_data_base_64` must be valid nul-terminated strings.
+WasmStatus register_module(uint64_t runtime_id,
+                           const char *module_name,
+                           const char *module_data_base_64);
+
+/// Registers the module in `data`, compiled or as WAT text, without a base64 round-trip.
+/// With `wasi`, every call gets a WASI environment of its own using the host's stdio.
+///
+/// # Safety
+/// `module_name` must be a valid nul-terminated string, `data` must point to `len` bytes.
+WasmStatus register_module_bytes(uint64_t runtime_id,
+                                 const char *module_name,
+                                 const uint8_t *data,
+                                 uintptr_t len,
+                                 bool wasi);
+
+/// # Safety
+/// `data` must be null or a string returned by this library that has not been freed yet.
 void free_ffi_string(char *data);
 
-bool is_module_registered(uint64_t runtime_id, const char *module_name);
+/// # Safety
+/// `module_name` must be a valid nul-terminated string, `registered` must be null or valid for
+/// writes.
+WasmStatus is_module_registered(uint64_t runtime_id, const char *module_name, bool *registered);
+
+/// # Safety
+/// `module_name`, `function.name` and every argument value must be valid nul-terminated strings,
+/// `function.args` must point to `function.args_len` arguments and `results` must be null or
+/// valid for writes. The results written to it must be released with [`free_wasm_results`].
+WasmStatus execute_module(uint64_t runtime_id,
+                          const char *module_name,
+                          WasmFunction function,
+                          WasmResults *results);
+
+/// # Safety
+/// `results` must come from [`execute_module`] and not have been freed yet.
+void free_wasm_results(WasmResults results);
 
-int32_t execute_module(uint64_t runtime_id, const char *module_name, WasmFunction function);
+/// Message of the last call on `runtime_id` that didn't return `Ok`, or null if there is none.
+/// Calls that don't take a runtime, or take one that doesn't exist, record their errors under
+/// 0. The message must be released with [`free_ffi_string`].
+char *last_error_message(uint64_t runtime_id);
 
 } // extern "C"
@@ -1,48 +1,195 @@
 #include "libwasmfaas.h"
 #include "iostream"
+#include "cstring"
 
-int main()
+// base64 of:
+// (module
+//   (func (export "answer") (result i32) (i32.const 42))
+//   (func (export "negate") (param i64) (result i64) (i64.sub (i64.const 0) (local.get 0)))
+//   (func (export "sum5") (param i32 i32 i32 i32 i32) (result i32)
+//     (i32.add (i32.add (i32.add (i32.add (local.get 0) (local.get 1)) (local.get 2)) (local.get 3)) (local.get 4))))
+const char *ARITY_MODULE =
+    "KG1vZHVsZQogIChmdW5jIChleHBvcnQgImFuc3dlciIpIChyZXN1bHQgaTMyKSAoaTMyLmNvbnN0IDQyKSkKICAoZnVu"
+    "YyAoZXhwb3J0ICJuZWdhdGUiKSAocGFyYW0gaTY0KSAocmVzdWx0IGk2NCkgKGk2NC5zdWIgKGk2NC5jb25zdCAwKSAo"
+    "bG9jYWwuZ2V0IDApKSkKICAoZnVuYyAoZXhwb3J0ICJzdW01IikgKHBhcmFtIGkzMiBpMzIgaTMyIGkzMiBpMzIpIChy"
+    "ZXN1bHQgaTMyKQogICAgKGkzMi5hZGQgKGkzMi5hZGQgKGkzMi5hZGQgKGkzMi5hZGQgKGxvY2FsLmdldCAwKSAobG9j"
+    "YWwuZ2V0IDEpKSAobG9jYWwuZ2V0IDIpKSAobG9jYWwuZ2V0IDMpKSAobG9jYWwuZ2V0IDQpKSkp";
+
+int failures = 0;
+
+void expect(const char *what, int64_t found, int64_t expected)
 {
-    uint64_t runtime_id = initialize_runtime();
-    uint64_t runtime2_id = initialize_runtime();
+    std::cout << what << " result " << found << '\n';
+    if (found != expected)
+    {
+        std::cout << "  expected " << expected << '\n';
+        failures++;
+    }
+}
 
-    char *sum_module_data = get_static_module_data(StaticModuleList::WasmSum);
-    const char *module_name = register_module(runtime_id, "sum", sum_module_data);
-    std::cout << "Registed " << module_name << " With " << runtime_id << '\n';
+// Prints the error recorded for a failed call and checks it is the expected one.
+void expect_status(const char *what, uint64_t runtime_id, WasmStatus found, WasmStatus expected)
+{
+    if (found != WasmStatus::Ok)
+    {
+        char *message = last_error_message(runtime_id);
+        std::cout << what << " failed: " << (message ? message : "no message") << '\n';
+        free_ffi_string(message);
+    }
+    if (found != expected)
+    {
+        std::cout << "  unexpected status " << static_cast<int>(found) << '\n';
+        failures++;
+    }
+}
 
-    char *div_module_data = get_static_module_data(StaticModuleList::WasmDiv);
-    const char *module_name2 = register_module(runtime2_id, "div", div_module_data);
-    std::cout << "Registed " << module_name2 << " With " << runtime2_id << '\n';
+WasmResults call(uint64_t runtime_id, const char *module, WasmFunction function)
+{
+    WasmResults results{nullptr, 0};
+    expect_status(function.name, runtime_id, execute_module(runtime_id, module, function, &results), WasmStatus::Ok);
+    return results;
+}
 
-    auto func = WasmFunction{};
-    func.name = "sum";
+int main()
+{
+    uint64_t runtime_id, runtime2_id;
+    expect_status("initialize", 0, initialize_runtime(&runtime_id), WasmStatus::Ok);
+    expect_status("initialize", 0, initialize_runtime(&runtime2_id), WasmStatus::Ok);
 
-    func.args[0] = WasmArg{
-        "10",
-        ArgType::I32,
-    };
+    char *sum_module_data, *div_module_data;
+    get_static_module_data(StaticModuleList::WasmSum, &sum_module_data);
+    expect_status("register", runtime_id, register_module(runtime_id, "sum", sum_module_data), WasmStatus::Ok);
+    std::cout << "Registed sum With " << runtime_id << '\n';
+
+    get_static_module_data(StaticModuleList::WasmDiv, &div_module_data);
+    expect_status("register", runtime2_id, register_module(runtime2_id, "div", div_module_data), WasmStatus::Ok);
+    std::cout << "Registed div With " << runtime2_id << '\n';
 
-    func.args[1] = WasmArg{
-        "10",
-        ArgType::I32,
+    WasmArg args[] = {
+        WasmArg{"10", ArgType::I32},
+        WasmArg{"10", ArgType::I32},
     };
+    auto func = WasmFunction{"sum", args, 2};
 
-    auto result = execute_module(runtime_id, "sum", func);
-    std::cout << runtime_id << " "
-              << "Sum result " << result << '\n';
+    auto result = call(runtime_id, "sum", func);
+    expect("Sum", result.values[0].data.i32, 20);
+    free_wasm_results(result);
 
     func.name = "div";
+    args[1] = WasmArg{"2", ArgType::I32};
+
+    auto result2 = call(runtime2_id, "div", func);
+    expect("Div", result2.values[0].data.i32, 5);
+    free_wasm_results(result2);
+
+    register_module(runtime_id, "arity", ARITY_MODULE);
+
+    // any registered module can be exported back, and registered again from raw bytes
+    const char *NEGATE_MODULE = "(module (func (export \"negate\") (param i32) (result i32) (i32.sub (i32.const 0) (local.get 0))))";
+    expect_status("register bytes", runtime_id, register_module_bytes(runtime_id, "raw", reinterpret_cast<const uint8_t *>(NEGATE_MODULE), strlen(NEGATE_MODULE), false), WasmStatus::Ok);
+    WasmArg three[] = {WasmArg{"3", ArgType::I32}};
+    auto raw = call(runtime_id, "raw", WasmFunction{"negate", three, 1});
+    expect("Raw negate", raw.values[0].data.i32, -3);
+    free_wasm_results(raw);
+
+    // nuls in export names can't reach C, they are dropped
+    const char *PICK_MODULE = "(module (func $f (export \"a\\00b\")) (func (export \"pick\") (result funcref) (ref.func $f)))";
+    expect_status("register pick", runtime_id, register_module_bytes(runtime_id, "pick", reinterpret_cast<const uint8_t *>(PICK_MODULE), strlen(PICK_MODULE), false), WasmStatus::Ok);
+    auto picked = call(runtime_id, "pick", WasmFunction{"pick", nullptr, 0});
+    expect("Picked name", strcmp(picked.values[0].data.reference, "ab"), 0);
+    free_wasm_results(picked);
 
-    func.args[1] = WasmArg{
-        "2",
-        ArgType::I32,
+    // WASI modules get an environment of their own per call, writing to the host's stdio
+    const char *WASI_MODULE = "(module (import \"wasi_snapshot_preview1\" \"fd_write\" (func $fd_write (param i32 i32 i32 i32) (result i32)))"
+                              " (memory (export \"memory\") 1) (data (i32.const 16) \"hi from wasi\\n\")"
+                              " (func (export \"greet\") (result i32) (i32.store (i32.const 0) (i32.const 16)) (i32.store (i32.const 4) (i32.const 13))"
+                              " (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))) (i32.load (i32.const 8))))";
+    const uint8_t *wasi_data = reinterpret_cast<const uint8_t *>(WASI_MODULE);
+    expect_status("register without wasi", runtime2_id, register_module_bytes(runtime2_id, "unlinked", wasi_data, strlen(WASI_MODULE), false), WasmStatus::Ok);
+    WasmResults greeted_none;
+    expect_status("call without wasi", runtime2_id, execute_module(runtime2_id, "unlinked", WasmFunction{"greet", nullptr, 0}, &greeted_none), WasmStatus::ExecutionFailed);
+    expect_status("register wasi", runtime2_id, register_module_bytes(runtime2_id, "greet", wasi_data, strlen(WASI_MODULE), true), WasmStatus::Ok);
+    auto greeted = call(runtime2_id, "greet", WasmFunction{"greet", nullptr, 0});
+    expect("Greeted bytes", greeted.values[0].data.i32, 13);
+    free_wasm_results(greeted);
+
+    char *exported;
+    expect_status("export", runtime_id, get_runtime_module_base64_data(runtime_id, "arity", &exported), WasmStatus::Ok);
+    expect("Exported arity", strcmp(exported, ARITY_MODULE), 0);
+    expect_status("register exported", runtime2_id, register_module(runtime2_id, "arity", exported), WasmStatus::Ok);
+    free_ffi_string(exported);
+
+    auto answer = call(runtime_id, "arity", WasmFunction{"answer", nullptr, 0});
+    expect("Answer", answer.values[0].data.i32, 42);
+    free_wasm_results(answer);
+
+    WasmArg one[] = {WasmArg{"-7", ArgType::I64}};
+    auto negate = call(runtime_id, "arity", WasmFunction{"negate", one, 1});
+    expect("Negate", negate.values[0].data.i64, 7);
+    free_wasm_results(negate);
+
+    WasmArg five[] = {
+        WasmArg{"1", ArgType::I32},
+        WasmArg{"2", ArgType::I32},
+        WasmArg{"3", ArgType::I32},
+        WasmArg{"4", ArgType::I32},
+        WasmArg{"5", ArgType::I32},
     };
+    auto sum5 = call(runtime_id, "arity", WasmFunction{"sum5", five, 5});
+    expect("Sum5", sum5.values[0].data.i32, 15);
+    free_wasm_results(sum5);
+
+    bool registered = true;
+    expect_status("is_module_registered", runtime_id, is_module_registered(runtime_id, "div", &registered), WasmStatus::Ok);
+    expect("Registered", registered, false);
+
+    WasmResults unused;
+    expect_status("missing module", runtime_id, execute_module(runtime_id, "div", func, &unused), WasmStatus::ModuleNotFound);
+    expect_status("missing runtime", 0, register_module(UINT64_MAX, "sum", sum_module_data), WasmStatus::RuntimeNotFound);
+    expect_status("missing function", runtime_id, execute_module(runtime_id, "arity", func, &unused), WasmStatus::FunctionNotFound);
+    expect_status("wrong arity", runtime_id, execute_module(runtime_id, "arity", WasmFunction{"sum5", args, 2}, &unused), WasmStatus::SignatureMismatch);
+    expect_status("invalid module", runtime_id, register_module(runtime_id, "broken", "AGFzbQ=="), WasmStatus::RegistrationFailed);
 
-    auto result2 = execute_module(runtime2_id, "div", func);
+    args[1] = WasmArg{"0", ArgType::I32};
+    expect_status("division by zero", runtime2_id, execute_module(runtime2_id, "div", func, &unused), WasmStatus::ExecutionFailed);
 
-    std::cout << runtime2_id << " "
-              << "Div result " << result2 << '\n';
+    uint64_t clone_id;
+    expect_status("clone", runtime_id, clone_runtime(runtime_id, &clone_id), WasmStatus::Ok);
+    expect_status("reset", runtime_id, reset_runtime(runtime_id), WasmStatus::Ok);
+    expect_status("reset module", runtime_id, is_module_registered(runtime_id, "sum", &registered), WasmStatus::Ok);
+    expect("Registered after reset", registered, false);
+
+    WasmStrings names;
+    expect_status("list modules", clone_id, list_modules(clone_id, &names), WasmStatus::Ok);
+    expect("Cloned modules", names.len, 4);
+    for (uintptr_t i = 0; i < names.len; i++)
+    {
+        std::cout << "Cloned " << names.values[i] << '\n';
+    }
+    free_wasm_strings(names);
+
+    auto cloned = call(clone_id, "arity", WasmFunction{"sum5", five, 5});
+    expect("Cloned sum5", cloned.values[0].data.i32, 15);
+    free_wasm_results(cloned);
+
+    uintptr_t count;
+    expect_status("count runtimes", 0, list_runtimes(nullptr, 0, &count), WasmStatus::Ok);
+    expect("Runtimes", count, 3);
+
+    uint64_t ids[3];
+    list_runtimes(ids, 3, &count);
+    expect("Clone id", ids[2], clone_id);
+
+    for (auto id : ids)
+    {
+        expect_status("destroy", id, destroy_runtime(id), WasmStatus::Ok);
+    }
+    expect_status("destroy twice", 0, destroy_runtime(clone_id), WasmStatus::RuntimeNotFound);
+    list_runtimes(nullptr, 0, &count);
+    expect("Runtimes after destroy", count, 0);
 
     free_ffi_string(sum_module_data);
     free_ffi_string(div_module_data);
-}
\ No newline at end of file
+
+    return failures;
+}
@@ -1,10 +1,11 @@
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
-use axum::{extract::Extension, routing::post, Router};
 use tokio::sync::Mutex;
-use wasmer::Store;
 use wasmfaas::{
-    module_store::ModuleStore, server::routes::register_function::register_function_handler,
+    compiler::{ArtifactCache, ModuleCompiler},
+    module_store::{ModuleStore, SharedModuleStore},
+    server,
+    storage::DirectoryStorage,
     ServerState,
 };
 
@@ -14,20 +15,30 @@ async fn main() {
     // `axum::Server` is a re-exporst of `hyper::Server`
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
 
-    let module_store = Arc::new(Mutex::new(ModuleStore::default()));
-    let wasm_store = Arc::new(Store::default());
+    let data_dir = std::env::var("WASMFAAS_DATA_DIR").unwrap_or_else(|_| "data".to_owned());
+    let storage = DirectoryStorage::open(&data_dir).expect("failed to open data directory");
+    let cache_dir = std::env::var("WASMFAAS_CACHE_DIR").unwrap_or_else(|_| "cache".to_owned());
+    let cache = ArtifactCache::open(&cache_dir).expect("failed to open cache directory");
+    let compiler = ModuleCompiler::default().with_cache(cache);
+
+    // Host directories modules may mount read-only, separated like `PATH`.
+    let host_dirs = std::env::var_os("WASMFAAS_HOST_DIRS")
+        .map(|dirs| std::env::split_paths(&dirs).collect())
+        .unwrap_or_default();
+
+    let module_store = ModuleStore::with_compiler(compiler)
+        .with_host_dirs(host_dirs)
+        .restore(Arc::new(storage))
+        .expect("failed to load modules from data directory");
+    let module_store = Arc::new(SharedModuleStore::new(module_store));
     let known_nodes = Arc::new(Mutex::new(HashMap::default()));
 
     let server_state = ServerState {
         module_store,
-        wasm_store,
         known_nodes,
     };
 
-    let app = Router::new()
-        .route("/register", post(register_function_handler))
-        // .route("/exec", post(execute_function_handler))
-        .layer(Extension(server_state));
+    let app = server::router(server_state);
 
     println!("Running at {}", addr);
     axum::Server::bind(&addr)
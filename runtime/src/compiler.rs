@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use wasmer::{BaseTunables, Module, Store};
+
+use crate::{
+    compile_wasm,
+    limits::{LimitingTunables, ResourceLimits},
+    metered_store,
+    module_store::content_hash,
+    storage::write_atomic,
+};
+
+/// Compiler and middlewares backing [`crate::metered_store`].
+const COMPILER: &str = "cranelift+metering";
+
+const VERSION_STAMP: &str = "RUNTIME_VERSION";
+
+/// Identifies the code generator; artifacts produced by another version are never reused.
+pub fn runtime_version() -> String {
+    format!(
+        "wasmfaas-{}+wasmer-{}+{}",
+        env!("CARGO_PKG_VERSION"),
+        wasmer::VERSION,
+        COMPILER
+    )
+}
+
+/// Compiles modules, reusing previously compiled artifacts from its cache.
+///
+/// Every module gets a store of its own: the metering middleware keeps per-module state
+/// and cannot be shared by two modules compiled on the same engine.
+#[derive(Clone)]
+pub struct ModuleCompiler {
+    new_store: fn() -> Store,
+    cache: Arc<ArtifactCache>,
+}
+
+impl Default for ModuleCompiler {
+    fn default() -> Self {
+        Self::new(metered_store)
+    }
+}
+
+impl ModuleCompiler {
+    /// Creates a compiler with an in-memory cache only.
+    pub fn new(new_store: fn() -> Store) -> Self {
+        Self {
+            new_store,
+            cache: Arc::new(ArtifactCache::in_memory()),
+        }
+    }
+
+    pub fn with_cache(mut self, cache: ArtifactCache) -> Self {
+        self.cache = Arc::new(cache);
+        self
+    }
+
+    pub fn cache(&self) -> &ArtifactCache {
+        &self.cache
+    }
+
+    /// Compiles `data` into a store enforcing `limits` on every instance of the module.
+    pub fn compile(&self, data: &[u8], limits: &ResourceLimits) -> anyhow::Result<Module> {
+        let engine = (self.new_store)().engine().clone();
+        let tunables = LimitingTunables::new(BaseTunables::for_target(engine.target()), *limits);
+        let store = Store::new_with_tunables(&*engine, tunables);
+
+        let key = self.cache.key(&store, data, limits);
+        if let Some(module) = self.cache.get(&store, &key) {
+            return Ok(module);
+        }
+
+        let module = compile_wasm(&store, data)?;
+        self.cache.put(&key, &module);
+        Ok(module)
+    }
+}
+
+/// Compiled modules an [`ArtifactCache`] keeps in memory unless configured otherwise.
+pub const DEFAULT_CACHE_CAPACITY: usize = 64;
+
+/// Content-addressed cache of compiled modules, keyed by wasm hash, compiler, engine target
+/// and runtime version. The most recently used artifacts are kept in memory and, when a
+/// directory is configured, all of them are serialized to disk so restarts skip compilation
+/// too. Failing to write an artifact doesn't fail the compilation; it is counted and the
+/// last error kept for [`ArtifactCache::last_write_error`].
+pub struct ArtifactCache {
+    dir: Option<PathBuf>,
+    capacity: usize,
+    modules: Mutex<LruModules>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    write_failures: AtomicU64,
+    last_write_error: Mutex<Option<String>>,
+}
+
+/// Modules with the tick they were last used at.
+#[derive(Default)]
+struct LruModules {
+    modules: HashMap<String, (Module, u64)>,
+    tick: u64,
+}
+
+impl Default for ArtifactCache {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            capacity: DEFAULT_CACHE_CAPACITY,
+            modules: Mutex::default(),
+            hits: AtomicU64::default(),
+            misses: AtomicU64::default(),
+            write_failures: AtomicU64::default(),
+            last_write_error: Mutex::default(),
+        }
+    }
+}
+
+impl ArtifactCache {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Keeps at most `capacity` modules in memory, evicting the least recently used first.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Opens an on-disk cache, wiping it when it was written by another runtime version.
+    pub fn open(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+        let stamp = dir.join(VERSION_STAMP);
+        let version = runtime_version();
+        if fs::read_to_string(&stamp).ok().as_deref() != Some(version.as_str()) {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "bin") {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("failed to remove {}", path.display()))?;
+                }
+            }
+            fs::write(&stamp, &version)
+                .with_context(|| format!("failed to write {}", stamp.display()))?;
+        }
+
+        Ok(Self {
+            dir: Some(dir),
+            ..Self::default()
+        })
+    }
+
+    pub fn key(&self, store: &Store, data: &[u8], limits: &ResourceLimits) -> String {
+        let target = store.engine().target();
+
+        let mut hasher = Sha256::new();
+        hasher.update(content_hash(data));
+        hasher.update(runtime_version());
+        hasher.update(target.triple().to_string());
+        hasher.update(format!("{:?}", target.cpu_features()));
+        hasher.update(format!("{:?}", limits));
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Artifacts that couldn't be written to the cache directory.
+    pub fn write_failures(&self) -> u64 {
+        self.write_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn last_write_error(&self) -> Option<String> {
+        self.last_write_error.lock().clone()
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.bin", key)))
+    }
+
+    fn get(&self, store: &Store, key: &str) -> Option<Module> {
+        if let Some(module) = self.modules.lock().get(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Some(module);
+        }
+
+        let module = self.path(key).and_then(|path| {
+            let bytes = fs::read(path).ok()?;
+            // SAFETY: only artifacts serialized by `put` for this runtime version and engine
+            // target are ever written to the cache directory.
+            unsafe { Module::deserialize(store, &bytes) }.ok()
+        });
+
+        match module {
+            Some(module) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                self.modules.lock().insert(key, &module, self.capacity);
+                Some(module)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn put(&self, key: &str, module: &Module) {
+        self.modules.lock().insert(key, module, self.capacity);
+
+        if let Some(path) = self.path(key) {
+            let written = module
+                .serialize()
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| write_atomic(&path, &bytes))
+                .with_context(|| format!("failed to cache {}", path.display()));
+            if let Err(err) = written {
+                self.write_failures.fetch_add(1, Ordering::Relaxed);
+                *self.last_write_error.lock() = Some(format!("{:#}", err));
+            }
+        }
+    }
+}
+
+impl LruModules {
+    fn get(&mut self, key: &str) -> Option<Module> {
+        self.tick += 1;
+        let (module, used) = self.modules.get_mut(key)?;
+        *used = self.tick;
+        Some(module.clone())
+    }
+
+    fn insert(&mut self, key: &str, module: &Module, capacity: usize) {
+        self.tick += 1;
+        self.modules
+            .insert(key.to_owned(), (module.clone(), self.tick));
+
+        while self.modules.len() > capacity {
+            let oldest = self
+                .modules
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone())
+                .expect("cache is not empty");
+            self.modules.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArtifactCache, ModuleCompiler};
+
+    static WASM_SUM: &[u8] = include_bytes!(r#"../../binaries/compiled/sum.wasm"#);
+    static WASM_DIV: &[u8] = include_bytes!(r#"../../binaries/compiled/div.wasm"#);
+
+    #[test]
+    fn test_memory_cache_evicts_least_recently_used() -> anyhow::Result<()> {
+        let compiler =
+            ModuleCompiler::default().with_cache(ArtifactCache::in_memory().with_capacity(1));
+        compiler.compile(WASM_SUM, &Default::default())?;
+        compiler.compile(WASM_SUM, &Default::default())?;
+        compiler.compile(WASM_DIV, &Default::default())?;
+        compiler.compile(WASM_SUM, &Default::default())?;
+        assert_eq!((compiler.cache().hits(), compiler.cache().misses()), (1, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_artifacts_survive_restarts() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let compiler = ModuleCompiler::default().with_cache(ArtifactCache::open(dir.path())?);
+        compiler.compile(WASM_SUM, &Default::default())?;
+        compiler.compile(WASM_SUM, &Default::default())?;
+        assert_eq!((compiler.cache().hits(), compiler.cache().misses()), (1, 1));
+
+        let restarted = ModuleCompiler::default().with_cache(ArtifactCache::open(dir.path())?);
+        let module = restarted.compile(WASM_SUM, &Default::default())?;
+        assert_eq!(
+            (restarted.cache().hits(), restarted.cache().misses()),
+            (1, 0)
+        );
+        assert!(module.exports().any(|export| export.name() == "sum"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_failures_are_reported() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let compiler = ModuleCompiler::default().with_cache(ArtifactCache::open(dir.path())?);
+        std::fs::remove_dir_all(dir.path())?;
+
+        compiler.compile(WASM_SUM, &Default::default())?;
+        assert_eq!(compiler.cache().write_failures(), 1);
+        let err = compiler
+            .cache()
+            .last_write_error()
+            .expect("write error is kept");
+        assert!(err.starts_with("failed to cache"), "{}", err);
+
+        compiler.compile(WASM_SUM, &Default::default())?;
+        assert_eq!(compiler.cache().hits(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_artifacts_are_dropped() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("stale.bin"), b"old artifact")?;
+        std::fs::write(dir.path().join(super::VERSION_STAMP), "wasmfaas-0.0.0")?;
+
+        ArtifactCache::open(dir.path())?;
+        assert!(!dir.path().join("stale.bin").exists());
+
+        Ok(())
+    }
+}
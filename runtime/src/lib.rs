@@ -1,22 +1,33 @@
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use chrono::NaiveDateTime;
-use module_store::ModuleStore;
+use module_store::SharedModuleStore;
 use tokio::sync::Mutex;
-use wasmer::{Module, Store};
+use wasmer::{CompilerConfig, Cranelift, Module, Store, Universal};
+use wasmer_middlewares::Metering;
 
+pub mod compiler;
+pub mod limits;
 pub mod module_store;
 pub mod runtime;
 pub mod server;
 pub mod sim_compat;
+pub mod storage;
 
 #[derive(Clone)]
 pub struct ServerState {
-    pub module_store: Arc<Mutex<ModuleStore>>,
-    pub wasm_store: Arc<Store>,
+    pub module_store: Arc<SharedModuleStore>,
     pub known_nodes: Arc<Mutex<HashMap<SocketAddr, NaiveDateTime>>>,
 }
 
+/// Creates a store compiling with cranelift and instruction metering, so every invocation
+/// can be bounded by a fuel limit.
+pub fn metered_store() -> Store {
+    let mut compiler = Cranelift::default();
+    compiler.push_middleware(Arc::new(Metering::new(u64::MAX, |_| 1)));
+    Store::new(&Universal::new(compiler).engine())
+}
+
 pub fn compile_wasm(store: &Store, data: &[u8]) -> Result<Module, wasmer::CompileError> {
     Module::new(store, data)
 }
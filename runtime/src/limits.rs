@@ -0,0 +1,304 @@
+use std::{
+    fmt,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use loupe::{MemoryUsage, MemoryUsageTracker};
+use serde::{Deserialize, Serialize};
+use wasmer::{
+    vm::{
+        Memory, MemoryError, MemoryStyle, Table, TableStyle, VMMemoryDefinition, VMTableDefinition,
+    },
+    BaseTunables, MemoryType, Module, Pages, TableType, Tunables,
+};
+
+/// Resources a single instance of a module may use. Unlimited when unset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceLimits {
+    /// Linear memory size, in 64KiB wasm pages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory_pages: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_table_elements: Option<u32>,
+    /// Instances of the module alive at the same time, i.e. concurrent invocations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_instances: Option<usize>,
+}
+
+impl ResourceLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Rejects modules whose memories or tables start out larger than the limits allow.
+    pub fn check(&self, module: &Module) -> Result<(), LimitError> {
+        let info = module.info();
+
+        if let Some(limit) = self.max_memory_pages {
+            for memory in info.memories.values() {
+                if memory.minimum.0 > limit {
+                    return Err(LimitError::MemoryPages {
+                        requested: memory.minimum.0,
+                        limit,
+                    });
+                }
+            }
+        }
+
+        if let Some(limit) = self.max_table_elements {
+            for table in info.tables.values() {
+                if table.minimum > limit {
+                    return Err(LimitError::TableElements {
+                        requested: table.minimum,
+                        limit,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    MemoryPages { requested: u32, limit: u32 },
+    TableElements { requested: u32, limit: u32 },
+    Instances { limit: usize },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::MemoryPages { requested, limit } => write!(
+                f,
+                "memory of {} pages exceeds the module limit of {} pages",
+                requested, limit
+            ),
+            LimitError::TableElements { requested, limit } => write!(
+                f,
+                "table of {} elements exceeds the module limit of {} elements",
+                requested, limit
+            ),
+            LimitError::Instances { limit } => {
+                write!(f, "module already has {} running instances", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// Caps the maximum of every memory and table a module defines, so `memory.grow` and
+/// `table.grow` fail (returning -1 to the guest) once they would cross the limit.
+pub struct LimitingTunables {
+    base: BaseTunables,
+    limits: ResourceLimits,
+}
+
+impl LimitingTunables {
+    pub fn new(base: BaseTunables, limits: ResourceLimits) -> Self {
+        Self { base, limits }
+    }
+
+    fn adjust_memory(&self, ty: &MemoryType) -> MemoryType {
+        let mut ty = *ty;
+        if let Some(limit) = self.limits.max_memory_pages {
+            ty.maximum = Some(ty.maximum.map_or(Pages(limit), |max| max.min(Pages(limit))));
+        }
+        ty
+    }
+
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        match ty.maximum {
+            Some(max) if ty.minimum > max => Err(MemoryError::Generic(
+                LimitError::MemoryPages {
+                    requested: ty.minimum.0,
+                    limit: max.0,
+                }
+                .to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn adjust_table(&self, ty: &TableType) -> TableType {
+        let mut ty = *ty;
+        if let Some(limit) = self.limits.max_table_elements {
+            ty.maximum = Some(ty.maximum.map_or(limit, |max| max.min(limit)));
+        }
+        ty
+    }
+
+    fn validate_table(&self, ty: &TableType) -> Result<(), String> {
+        match ty.maximum {
+            Some(max) if ty.minimum > max => Err(LimitError::TableElements {
+                requested: ty.minimum,
+                limit: max,
+            }
+            .to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl MemoryUsage for LimitingTunables {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        std::mem::size_of_val(self) + self.base.size_of_val(tracker)
+            - std::mem::size_of_val(&self.base)
+    }
+}
+
+impl Tunables for LimitingTunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(&self.adjust_memory(memory))
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(&self.adjust_table(table))
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<Arc<dyn Memory>, MemoryError> {
+        let ty = self.adjust_memory(ty);
+        self.validate_memory(&ty)?;
+        self.base.create_host_memory(&ty, style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<Arc<dyn Memory>, MemoryError> {
+        let ty = self.adjust_memory(ty);
+        self.validate_memory(&ty)?;
+        self.base
+            .create_vm_memory(&ty, style, vm_definition_location)
+    }
+
+    fn create_host_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+    ) -> Result<Arc<dyn Table>, String> {
+        let ty = self.adjust_table(ty);
+        self.validate_table(&ty)?;
+        self.base.create_host_table(&ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<Arc<dyn Table>, String> {
+        let ty = self.adjust_table(ty);
+        self.validate_table(&ty)?;
+        self.base
+            .create_vm_table(&ty, style, vm_definition_location)
+    }
+}
+
+/// Counts the live instances of a module version. Clones share the count.
+#[derive(Debug, Clone, Default)]
+pub struct InstanceCounter {
+    running: Arc<AtomicUsize>,
+}
+
+impl InstanceCounter {
+    /// Reserves a slot for a new instance, released when the guard is dropped.
+    pub fn acquire(&self, limit: Option<usize>) -> Result<InstanceGuard, LimitError> {
+        let running = self.running.fetch_add(1, Ordering::AcqRel);
+        let guard = InstanceGuard {
+            running: self.running.clone(),
+        };
+
+        match limit {
+            Some(limit) if running >= limit => Err(LimitError::Instances { limit }),
+            _ => Ok(guard),
+        }
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Acquire)
+    }
+}
+
+pub struct InstanceGuard {
+    running: Arc<AtomicUsize>,
+}
+
+impl Drop for InstanceGuard {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::{imports, Instance, Value};
+
+    use super::{InstanceCounter, LimitError, ResourceLimits};
+    use crate::compiler::ModuleCompiler;
+
+    static WAT_GROW: &[u8] = br#"(module
+        (memory 1)
+        (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0))))"#;
+
+    #[test]
+    fn test_memory_limits() -> anyhow::Result<()> {
+        let compiler = ModuleCompiler::default();
+        let limits = ResourceLimits {
+            max_memory_pages: Some(2),
+            ..Default::default()
+        };
+
+        let module = compiler.compile(WAT_GROW, &limits)?;
+        limits.check(&module)?;
+        let instance = Instance::new(&module, &imports! {})?;
+        let grow = instance.exports.get_function("grow")?;
+        assert_eq!(grow.call(&[Value::I32(1)])?[0], Value::I32(1));
+        assert_eq!(grow.call(&[Value::I32(1)])?[0], Value::I32(-1));
+
+        let limits = ResourceLimits {
+            max_memory_pages: Some(0),
+            ..Default::default()
+        };
+        let module = compiler.compile(WAT_GROW, &limits)?;
+        assert_eq!(
+            limits.check(&module),
+            Err(LimitError::MemoryPages {
+                requested: 1,
+                limit: 0
+            })
+        );
+        assert!(Instance::new(&module, &imports! {}).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_instance_counter() {
+        let counter = InstanceCounter::default();
+
+        let first = counter.acquire(Some(1)).unwrap();
+        assert_eq!(
+            counter.acquire(Some(1)).err(),
+            Some(LimitError::Instances { limit: 1 })
+        );
+        assert_eq!(counter.running(), 1);
+
+        drop(first);
+        assert!(counter.acquire(Some(1)).is_ok());
+        assert_eq!(counter.running(), 0);
+    }
+}
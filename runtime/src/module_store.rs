@@ -1,53 +1,444 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
 
-use wasmer::{imports, Instance};
+use anyhow::Context;
+use chrono::{NaiveDateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasmer::{imports, ChainableNamedResolver, ExternType, Instance, NamedResolver};
 use wasmer::{ImportObject, Module};
 use wasmer_wasi::{WasiEnv, WasiStateBuilder};
 
+use crate::storage::{MemoryStorage, ModuleStorage, VersionRecord};
+use crate::{
+    compiler::ModuleCompiler,
+    limits::{InstanceCounter, ResourceLimits},
+    runtime::filesystem::{Mount, Mounts},
+    runtime::instance_pool::{reuse_blocker, InstancePolicy, InstancePool, DEFAULT_POOL_SIZE},
+    runtime::wit::Interface,
+};
+
+/// Alias that always points at the most recently registered version of a module.
+pub const LATEST: &str = "latest";
+
 #[derive(Debug, Clone)]
 pub struct ModulePackage {
     pub module: Module,
+    /// The bytes `module` was compiled from, as registered.
+    pub data: Arc<[u8]>,
     pub wasi: bool,
+    pub config: ModuleConfig,
     pub imports: ImportObject,
+    /// The store modules this package resolved its imports against, by name. Every instance
+    /// of the package gets instances of its own of them, see [`ModulePackage::instantiate`].
+    pub dependencies: BTreeMap<String, Arc<ModulePackage>>,
+    /// Live instances, checked against `config.limits.max_instances`.
+    pub instances: InstanceCounter,
+    pub pool: Arc<InstancePool>,
+    /// `config.mounts`, validated against the store's allowed host directories.
+    pub mounts: Arc<Mounts>,
+    /// `config.wit`, parsed and checked against the module exports.
+    pub interface: Option<Arc<Interface>>,
 }
 
 impl ModulePackage {
-    pub fn new(module: &Module, store: &ModuleStore, wasi: bool) -> anyhow::Result<Self> {
-        let mut imports = module.imports().collect::<Vec<_>>();
-        imports.sort_unstable_by_key(|i| i.module().to_string());
-        imports.dedup();
-
-        let mut import_object = if wasi {
+    pub fn new(
+        name: &str,
+        module: &Module,
+        data: Arc<[u8]>,
+        store: &ModuleStore,
+        wasi: bool,
+        config: ModuleConfig,
+    ) -> anyhow::Result<Self> {
+        let import_object = if wasi {
             let wasi_state = WasiStateBuilder::default().build()?;
             let mut wasi_env = WasiEnv::new(wasi_state);
-            let imports = wasi_env.import_object(&module)?;
-            imports
+            wasi_env.import_object(module)?
         } else {
-            let imports = imports! {};
-            imports
+            imports! {}
         };
 
-        for import in imports {
-            if let Some(imported_module) = store.get(import.module()) {
-                let imports = &imported_module.imports;
-                let module = &imported_module.module;
-                let instance = Instance::new(module, imports)?;
-                let exports = instance.exports;
-                import_object.register(import.name(), exports);
+        let mounts = Mounts::new(&config.mounts, &store.host_dirs)?;
+        let interface = match &config.wit {
+            Some(wit) => {
+                let interface = Interface::parse(wit)?;
+                interface.check(module)?;
+                Some(Arc::new(interface))
             }
+            None => None,
+        };
+        let mut dependencies = BTreeMap::new();
+
+        for import in module.imports() {
+            let imported_module = match store.latest(import.module()) {
+                Some(imported_module) => imported_module,
+                None => continue,
+            };
+
+            let export = imported_module
+                .module
+                .exports()
+                .find(|export| export.name() == import.name())
+                .ok_or_else(|| ModuleStoreError::UnresolvedImport {
+                    module: import.module().to_owned(),
+                    name: import.name().to_owned(),
+                })?;
+
+            if let (ExternType::Function(expected), ExternType::Function(found)) =
+                (import.ty(), export.ty())
+            {
+                if expected != found {
+                    return Err(ModuleStoreError::IncompatibleImport {
+                        module: import.module().to_owned(),
+                        name: import.name().to_owned(),
+                    }
+                    .into());
+                }
+            }
+
+            dependencies.insert(import.module().to_owned(), imported_module.clone());
         }
 
-        Ok(ModulePackage {
+        if config.instance_policy == InstancePolicy::Reuse {
+            if let Some(reason) = pool_blocker(module, wasi, &dependencies) {
+                return Err(ModuleStoreError::CannotReuseInstances {
+                    module: name.to_owned(),
+                    reason,
+                }
+                .into());
+            }
+        }
+
+        let pool = InstancePool::new(
+            config.instance_policy,
+            config.pool_size.unwrap_or(DEFAULT_POOL_SIZE),
+        );
+        let package = ModulePackage {
             module: module.clone(),
+            data,
             wasi,
+            config,
             imports: import_object,
+            dependencies,
+            instances: InstanceCounter::default(),
+            pool: Arc::new(pool),
+            mounts: Arc::new(mounts),
+            interface,
+        };
+        package.pool.fill(&package)?;
+
+        Ok(package)
+    }
+
+    /// Instantiates the module against `imports`, and its dependencies against their own
+    /// registration imports. Dependency instances aren't shared, so the fuel and state of a
+    /// call stay within the instances it was given.
+    pub fn instantiate(
+        &self,
+        imports: impl NamedResolver + Send + Sync,
+    ) -> anyhow::Result<LinkedInstance> {
+        let mut linked = ImportObject::new();
+        let mut dependencies = Vec::new();
+        for (name, dependency) in &self.dependencies {
+            let instance = dependency.instantiate(&dependency.imports)?;
+            linked.register(name, instance.instance.exports.clone());
+            dependencies.extend(instance.dependencies);
+            dependencies.push(instance.instance);
+        }
+
+        Ok(LinkedInstance {
+            instance: Instance::new(&self.module, &imports.chain_back(linked))?,
+            dependencies,
+        })
+    }
+}
+
+/// Explains why instances of `module` can't be reset between calls, if they can't. Pooled
+/// instances are reset together with their dependency instances, so those must allow it too.
+fn pool_blocker(
+    module: &Module,
+    wasi: bool,
+    dependencies: &BTreeMap<String, Arc<ModulePackage>>,
+) -> Option<String> {
+    if wasi {
+        return Some("WASI modules keep state outside of the instance".to_owned());
+    }
+    if let Some(reason) = reuse_blocker(module) {
+        return Some(reason);
+    }
+
+    dependencies.iter().find_map(|(name, dependency)| {
+        pool_blocker(
+            &dependency.module,
+            dependency.wasi,
+            &dependency.dependencies,
+        )
+        .map(|reason| format!("its dependency {} can't be reset: {}", name, reason))
+    })
+}
+
+/// An instance with the dependency instances it imports from. Imported functions don't keep
+/// the instance exporting them alive, so the dependencies must outlive `instance`.
+pub struct LinkedInstance {
+    pub instance: Instance,
+    /// Every instance `instance` (transitively) calls into, dependencies first.
+    pub dependencies: Vec<Instance>,
+}
+
+/// Per-module settings chosen at registration time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleConfig {
+    /// Fuel (executed instructions) available to each invocation unless the request overrides
+    /// it. Unlimited when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<u64>,
+    #[serde(default, skip_serializing_if = "ResourceLimits::is_unlimited")]
+    pub limits: ResourceLimits,
+    #[serde(default, skip_serializing_if = "InstancePolicy::is_fresh")]
+    pub instance_policy: InstancePolicy,
+    /// Instances kept warm under [`InstancePolicy::Reuse`], [`DEFAULT_POOL_SIZE`] when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_size: Option<usize>,
+    /// Bytes of stdout and stderr kept per invocation, 1MiB each when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<usize>,
+    /// WASI arguments following the program name, unless the invocation passes its own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// WASI environment variables every invocation starts with.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Directories preopened for WASI modules.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<Mount>,
+    /// WIT interface describing the module exports, lets invocations pass typed JSON values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wit: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleStoreError {
+    NotFound(String),
+    /// Module names can't contain the `@`/`:` reference separators, alias names must be plain words.
+    InvalidName(String),
+    VersionNotFound {
+        module: String,
+        version: u64,
+    },
+    /// `latest` is moved by registrations and can't be removed.
+    ReservedAlias(String),
+    /// The module is still imported by other registered modules.
+    HasDependents {
+        module: String,
+        dependents: Vec<String>,
+    },
+    /// Registering the module would make it (transitively) import itself.
+    DependencyCycle(String),
+    UnresolvedImport {
+        module: String,
+        name: String,
+    },
+    IncompatibleImport {
+        module: String,
+        name: String,
+    },
+    /// A dependent module could not be rebuilt against the new version of a dependency.
+    DependentRebuild {
+        module: String,
+        dependent: String,
+        reason: String,
+    },
+    /// The module asked for instance reuse but its state can't be reset between calls.
+    CannotReuseInstances {
+        module: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ModuleStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleStoreError::NotFound(name) => write!(f, "module not found: {}", name),
+            ModuleStoreError::InvalidName(name) => write!(f, "invalid name: {}", name),
+            ModuleStoreError::VersionNotFound { module, version } => {
+                write!(f, "module {} has no version {}", module, version)
+            }
+            ModuleStoreError::ReservedAlias(alias) => {
+                write!(f, "alias {} is managed by the runtime", alias)
+            }
+            ModuleStoreError::HasDependents { module, dependents } => write!(
+                f,
+                "module {} is imported by: {}",
+                module,
+                dependents.join(", ")
+            ),
+            ModuleStoreError::DependencyCycle(name) => {
+                write!(f, "module {} would depend on itself", name)
+            }
+            ModuleStoreError::UnresolvedImport { module, name } => {
+                write!(
+                    f,
+                    "import {}.{} is not exported by {}",
+                    module, name, module
+                )
+            }
+            ModuleStoreError::IncompatibleImport { module, name } => write!(
+                f,
+                "import {}.{} does not match the signature exported by {}",
+                module, name, module
+            ),
+            ModuleStoreError::DependentRebuild {
+                module,
+                dependent,
+                reason,
+            } => write!(
+                f,
+                "replacing {} would break dependent module {}: {}",
+                module, dependent, reason
+            ),
+            ModuleStoreError::CannotReuseInstances { module, reason } => write!(
+                f,
+                "instances of {} can't be reused because {}",
+                module, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModuleStoreError {}
+
+/// Selects one version of a module, parsed from `name`, `name@version` or `name:alias`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleRef {
+    pub name: String,
+    pub selector: VersionSelector,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionSelector {
+    Version(u64),
+    Alias(String),
+}
+
+impl ModuleRef {
+    pub fn parse(reference: &str) -> Option<Self> {
+        let (name, selector) = if let Some((name, version)) = reference.split_once('@') {
+            (name, VersionSelector::Version(version.parse().ok()?))
+        } else if let Some((name, alias)) = reference.split_once(':') {
+            (name, VersionSelector::Alias(alias.to_owned()))
+        } else {
+            (reference, VersionSelector::Alias(LATEST.to_owned()))
+        };
+
+        if !is_valid_name(name) {
+            return None;
+        }
+
+        Some(Self {
+            name: name.to_owned(),
+            selector,
         })
     }
 }
 
-#[derive(Default)]
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+pub fn content_hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// An immutable registration of a module.
+#[derive(Debug, Clone)]
+pub struct ModuleVersion {
+    pub version: u64,
+    pub hash: String,
+    pub created_at: NaiveDateTime,
+    pub sequence: u64,
+    pub package: Arc<ModulePackage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleVersionInfo {
+    pub name: String,
+    pub version: u64,
+    pub hash: String,
+    pub wasi: bool,
+    pub config: ModuleConfig,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ModuleEntry {
+    versions: BTreeMap<u64, ModuleVersion>,
+    aliases: BTreeMap<String, u64>,
+}
+
+impl ModuleEntry {
+    pub fn latest(&self) -> &ModuleVersion {
+        &self.versions[&self.aliases[LATEST]]
+    }
+
+    pub fn versions(&self) -> impl Iterator<Item = &ModuleVersion> {
+        self.versions.values()
+    }
+
+    pub fn aliases(&self) -> &BTreeMap<String, u64> {
+        &self.aliases
+    }
+
+    pub fn resolve(&self, selector: &VersionSelector) -> Option<&ModuleVersion> {
+        let version = match selector {
+            VersionSelector::Version(version) => *version,
+            VersionSelector::Alias(alias) => *self.aliases.get(alias)?,
+        };
+        self.versions.get(&version)
+    }
+
+    fn dependencies(&self) -> impl Iterator<Item = &String> {
+        self.versions
+            .values()
+            .flat_map(|version| version.package.dependencies.keys())
+    }
+}
+
+impl ModuleVersion {
+    pub fn info(&self, name: &str) -> ModuleVersionInfo {
+        ModuleVersionInfo {
+            name: name.to_owned(),
+            version: self.version,
+            hash: self.hash.clone(),
+            wasi: self.package.wasi,
+            config: self.package.config.clone(),
+            created_at: self.created_at,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct ModuleStore {
-    store: HashMap<String, ModulePackage>,
+    compiler: ModuleCompiler,
+    storage: Arc<dyn ModuleStorage>,
+    store: HashMap<String, ModuleEntry>,
+    sequence: u64,
+    /// Host directories modules may mount, read-only.
+    host_dirs: Arc<[PathBuf]>,
+}
+
+impl Default for ModuleStore {
+    fn default() -> Self {
+        Self::with_compiler(ModuleCompiler::default())
+    }
 }
 
 impl ModuleStore {
@@ -55,19 +446,718 @@ impl ModuleStore {
         Self::default()
     }
 
-    pub fn add(&mut self, name: impl AsRef<str>, module: Module, wasi: bool) -> anyhow::Result<()> {
-        let name = name.as_ref().to_string();
-        let package = ModulePackage::new(&module, self, wasi)?;
-        self.store.insert(name, package);
+    /// Creates an empty, in-memory module store that compiles modules with `compiler`.
+    pub fn with_compiler(compiler: ModuleCompiler) -> Self {
+        Self {
+            compiler,
+            storage: Arc::new(MemoryStorage::default()),
+            store: HashMap::default(),
+            sequence: 0,
+            host_dirs: Arc::new([]),
+        }
+    }
+
+    /// Copies the store into one backed by memory storage holding what this store saved, so
+    /// later changes to either store don't reach the other.
+    pub fn fork(&self) -> anyhow::Result<Self> {
+        let storage = MemoryStorage::with_modules(self.storage.load()?);
+        Ok(Self {
+            storage: Arc::new(storage),
+            ..self.clone()
+        })
+    }
+
+    /// Lets modules mount the given host directories, and anything inside them, read-only.
+    pub fn with_host_dirs(mut self, host_dirs: Vec<PathBuf>) -> Self {
+        self.host_dirs = host_dirs.into();
+        self
+    }
+
+    /// Opens a module store backed by `storage`, recompiling every version it holds.
+    pub fn open(compiler: ModuleCompiler, storage: Arc<dyn ModuleStorage>) -> anyhow::Result<Self> {
+        Self::with_compiler(compiler).restore(storage)
+    }
+
+    /// Backs this empty store with `storage`, recompiling every version it holds.
+    ///
+    /// Versions are replayed in registration order so imports resolve the same way they did
+    /// when the modules were registered. Fails if any version no longer compiles or links, or
+    /// an alias can't be restored.
+    pub fn restore(self, storage: Arc<dyn ModuleStorage>) -> anyhow::Result<Self> {
+        let modules = storage.load()?;
+        let mut module_store = Self { storage, ..self };
+
+        let mut versions = modules
+            .iter()
+            .flat_map(|module| {
+                module
+                    .versions
+                    .iter()
+                    .map(move |(record, data)| (&module.name, record, data))
+            })
+            .collect::<Vec<_>>();
+        versions.sort_unstable_by_key(|(_, record, _)| record.sequence);
+
+        for (name, record, data) in versions {
+            module_store.sequence = module_store.sequence.max(record.sequence + 1);
+
+            module_store
+                .insert_version(name, record.clone(), data)
+                .and_then(|_| module_store.apply_alias(name, LATEST, record.version))
+                .with_context(|| format!("failed to restore module {}@{}", name, record.version))?;
+        }
+
+        for module in &modules {
+            for (alias, version) in &module.aliases {
+                module_store
+                    .apply_alias(&module.name, alias, *version)
+                    .with_context(|| {
+                        format!("failed to restore alias {}:{}", module.name, alias)
+                    })?;
+            }
+        }
+
+        Ok(module_store)
+    }
+
+    /// Registers a new immutable version of `name` and points `latest` at it.
+    ///
+    /// Registering bytes identical to an existing version reuses that version instead of
+    /// creating a new one. Modules importing `name` are rebuilt against the new version; if
+    /// any of them can no longer be linked the registration is rejected and the store is
+    /// left untouched.
+    pub fn add(
+        &mut self,
+        name: impl AsRef<str>,
+        data: &[u8],
+        wasi: bool,
+    ) -> anyhow::Result<ModuleVersionInfo> {
+        self.add_with_config(name, data, wasi, ModuleConfig::default())
+    }
+
+    /// Same as [`ModuleStore::add`], with explicit per-module settings. Identical bytes with a
+    /// different configuration create a new version.
+    pub fn add_with_config(
+        &mut self,
+        name: impl AsRef<str>,
+        data: &[u8],
+        wasi: bool,
+        config: ModuleConfig,
+    ) -> anyhow::Result<ModuleVersionInfo> {
+        let name = name.as_ref();
+        if !is_valid_name(name) {
+            return Err(ModuleStoreError::InvalidName(name.to_owned()).into());
+        }
+
+        let hash = content_hash(data);
+        let existing = self.store.get(name).and_then(|entry| {
+            entry
+                .versions()
+                .find(|version| {
+                    version.hash == hash
+                        && version.package.wasi == wasi
+                        && version.package.config == config
+                })
+                .map(|version| version.version)
+        });
+
+        let mut staged = self.clone();
+
+        let mut record = None;
+        let version = match existing {
+            Some(version) => version,
+            None => {
+                let version = self
+                    .store
+                    .get(name)
+                    .and_then(|entry| entry.versions.keys().next_back())
+                    .map_or(1, |last| last + 1);
+                let new_record = VersionRecord {
+                    version,
+                    hash,
+                    wasi,
+                    config,
+                    created_at: Utc::now().naive_utc(),
+                    sequence: self.sequence,
+                };
+
+                staged.insert_version(name, new_record.clone(), data)?;
+                staged.sequence += 1;
+                record = Some(new_record);
+                version
+            }
+        };
+
+        // Persist only once every in-memory step succeeded, so a rejected registration
+        // leaves nothing behind.
+        staged.apply_alias(name, LATEST, version)?;
+        if let Some(record) = &record {
+            self.storage.save_version(name, record, data)?;
+        }
+        self.storage
+            .save_aliases(name, &staged.store[name].aliases)?;
+        *self = staged;
+
+        let entry = &self.store[name];
+        Ok(entry.versions[&version].info(name))
+    }
+
+    /// Registers a new version of a module that must already exist.
+    pub fn replace(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        wasi: bool,
+    ) -> anyhow::Result<ModuleVersionInfo> {
+        if !self.contains_key(name) {
+            return Err(ModuleStoreError::NotFound(name.to_owned()).into());
+        }
+
+        self.add(name, data, wasi)
+    }
+
+    fn insert_version(
+        &mut self,
+        name: &str,
+        record: VersionRecord,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let module = self.compiler.compile(data, &record.config.limits)?;
+        record.config.limits.check(&module)?;
+        let package =
+            ModulePackage::new(name, &module, data.into(), self, record.wasi, record.config)?;
+
+        let dependents = self.transitive_dependents(name);
+        if package.dependencies.contains_key(name)
+            || package.dependencies.keys().any(|d| dependents.contains(d))
+        {
+            return Err(ModuleStoreError::DependencyCycle(name.to_owned()).into());
+        }
+
+        let entry = self.store.entry(name.to_owned()).or_default();
+        entry.versions.insert(
+            record.version,
+            ModuleVersion {
+                version: record.version,
+                hash: record.hash,
+                created_at: record.created_at,
+                sequence: record.sequence,
+                package: Arc::new(package),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Points `alias` at an existing version. Moving `latest` rebuilds the module's dependents.
+    pub fn set_alias(&mut self, name: &str, alias: &str, version: u64) -> anyhow::Result<()> {
+        let mut staged = self.clone();
+        staged.apply_alias(name, alias, version)?;
+        self.storage
+            .save_aliases(name, &staged.store[name].aliases)?;
+        *self = staged;
 
         Ok(())
     }
 
-    pub fn get(&self, name: &str) -> Option<&ModulePackage> {
+    /// Moves an alias without persisting it. Can leave the store half updated on error, so it
+    /// is only ever called on a staged copy.
+    fn apply_alias(&mut self, name: &str, alias: &str, version: u64) -> anyhow::Result<()> {
+        if !is_valid_name(alias) {
+            return Err(ModuleStoreError::InvalidName(alias.to_owned()).into());
+        }
+
+        let entry = self
+            .store
+            .get_mut(name)
+            .ok_or_else(|| ModuleStoreError::NotFound(name.to_owned()))?;
+        if !entry.versions.contains_key(&version) {
+            return Err(ModuleStoreError::VersionNotFound {
+                module: name.to_owned(),
+                version,
+            }
+            .into());
+        }
+
+        let previous = entry.aliases.insert(alias.to_owned(), version);
+        if alias == LATEST && previous != Some(version) {
+            self.rebuild_dependents(name)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_alias(&mut self, name: &str, alias: &str) -> anyhow::Result<()> {
+        if alias == LATEST {
+            return Err(ModuleStoreError::ReservedAlias(alias.to_owned()).into());
+        }
+
+        let entry = self
+            .store
+            .get(name)
+            .ok_or_else(|| ModuleStoreError::NotFound(name.to_owned()))?;
+        let mut aliases = entry.aliases.clone();
+        aliases.remove(alias);
+
+        self.storage.save_aliases(name, &aliases)?;
+        self.store.get_mut(name).expect("module exists").aliases = aliases;
+
+        Ok(())
+    }
+
+    /// Rebuilds every module that (transitively) imports `name`, dependencies first.
+    fn rebuild_dependents(&mut self, name: &str) -> anyhow::Result<()> {
+        let mut pending = self.transitive_dependents(name);
+
+        while !pending.is_empty() {
+            let ready = pending
+                .iter()
+                .find(|dependent| {
+                    self.store[*dependent]
+                        .dependencies()
+                        .all(|d| !pending.contains(d))
+                })
+                .cloned()
+                .expect("dependency graph is acyclic");
+
+            let versions = self.store[&ready]
+                .versions()
+                .map(|version| (version.version, version.package.clone()))
+                .collect::<Vec<_>>();
+
+            for (version, old) in versions {
+                // Also rechecks that reused instances can still be reset with the new dependency.
+                let mut rebuilt = ModulePackage::new(
+                    &ready,
+                    &old.module,
+                    old.data.clone(),
+                    self,
+                    old.wasi,
+                    old.config.clone(),
+                )
+                .map_err(|err| ModuleStoreError::DependentRebuild {
+                    module: name.to_owned(),
+                    dependent: ready.clone(),
+                    reason: err.to_string(),
+                })?;
+                // Calls already running on the old package still count against the limit.
+                rebuilt.instances = old.instances.clone();
+
+                let entry = self.store.get_mut(&ready).expect("dependent is registered");
+                entry
+                    .versions
+                    .get_mut(&version)
+                    .expect("version exists")
+                    .package = Arc::new(rebuilt);
+            }
+
+            pending.remove(&ready);
+        }
+
+        Ok(())
+    }
+
+    /// Removes a module with all of its versions, refusing to do so while other modules
+    /// still import it.
+    pub fn remove(&mut self, name: &str) -> anyhow::Result<ModuleEntry> {
+        if !self.contains_key(name) {
+            return Err(ModuleStoreError::NotFound(name.to_owned()).into());
+        }
+
+        let dependents = self.dependents(name);
+        if !dependents.is_empty() {
+            return Err(ModuleStoreError::HasDependents {
+                module: name.to_owned(),
+                dependents,
+            }
+            .into());
+        }
+
+        self.storage.remove_module(name)?;
+        Ok(self.store.remove(name).expect("module exists"))
+    }
+
+    /// Names of the modules that directly import `name`, sorted.
+    pub fn dependents(&self, name: &str) -> Vec<String> {
+        let mut dependents = self
+            .store
+            .iter()
+            .filter(|(_, entry)| entry.dependencies().any(|d| d == name))
+            .map(|(dependent, _)| dependent.clone())
+            .collect::<Vec<_>>();
+        dependents.sort_unstable();
+        dependents
+    }
+
+    fn transitive_dependents(&self, name: &str) -> HashSet<String> {
+        let mut found = HashSet::new();
+        let mut queue = vec![name.to_owned()];
+
+        while let Some(current) = queue.pop() {
+            for dependent in self.dependents(&current) {
+                if found.insert(dependent.clone()) {
+                    queue.push(dependent);
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Looks up a module version from a `name`, `name@version` or `name:alias` reference.
+    pub fn get(&self, reference: &str) -> Option<&Arc<ModulePackage>> {
+        self.get_version(reference).map(|version| &version.package)
+    }
+
+    pub fn get_version(&self, reference: &str) -> Option<&ModuleVersion> {
+        let reference = ModuleRef::parse(reference)?;
+        self.store
+            .get(&reference.name)?
+            .resolve(&reference.selector)
+    }
+
+    pub fn latest(&self, name: &str) -> Option<&Arc<ModulePackage>> {
+        self.store.get(name).map(|entry| &entry.latest().package)
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&ModuleEntry> {
         self.store.get(name)
     }
 
     pub fn contains_key(&self, name: &str) -> bool {
         self.store.contains_key(name)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ModuleEntry)> {
+        self.store.iter()
+    }
+}
+
+/// Module store shared by the server. Readers take cheap snapshots and never wait on
+/// registrations: writers apply their change to a copy on the blocking pool, compiling
+/// outside of any lock readers take, and then publish it. Writers are serialized.
+pub struct SharedModuleStore {
+    current: Arc<RwLock<Arc<ModuleStore>>>,
+    writer: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl SharedModuleStore {
+    pub fn new(module_store: ModuleStore) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(module_store))),
+            writer: Default::default(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<ModuleStore> {
+        self.current.read().clone()
+    }
+
+    /// Runs `change` against a copy of the store, publishing the copy only if it succeeds.
+    /// Once started, the change runs to completion and is published even if the returned
+    /// future is dropped, since it may already have persisted something.
+    pub async fn update<T, F>(&self, change: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut ModuleStore) -> anyhow::Result<T> + Send + 'static,
+    {
+        let writer = self.writer.clone().lock_owned().await;
+        let current = self.current.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let _writer = writer;
+            let mut staged = ModuleStore::clone(&current.read());
+
+            let result = change(&mut staged);
+            if result.is_ok() {
+                *current.write() = Arc::new(staged);
+            }
+            result
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ModuleConfig, ModuleStore, ModuleStoreError, SharedModuleStore};
+    use crate::{
+        compiler::ModuleCompiler,
+        runtime::instance_pool::InstancePolicy,
+        storage::{MemoryStorage, ModuleStorage},
+    };
+
+    static WASM_SUM: &[u8] = include_bytes!(r#"../../binaries/compiled/sum.wasm"#);
+    static WASM_DIV: &[u8] = include_bytes!(r#"../../binaries/compiled/div.wasm"#);
+    static WASM_IMPORT: &[u8] = include_bytes!(r#"../../binaries/compiled/import.wasm"#);
+
+    static WAT_FAKE_SUM: &[u8] =
+        br#"(module (func (export "sum") (param i32 i32) (result i32) i32.const 100))"#;
+
+    fn call_div_sum(module_store: &ModuleStore) -> anyhow::Result<i32> {
+        let package = module_store.get("import").unwrap();
+        let linked = package.instantiate(&package.imports)?;
+        let div_sum = linked
+            .instance
+            .exports
+            .get_native_function::<(i32, i32), i32>("div_sum")?;
+        Ok(div_sum.call(10, 10)?)
+    }
+
+    fn call_sum(module_store: &ModuleStore, reference: &str) -> anyhow::Result<i32> {
+        let package = module_store.get(reference).unwrap();
+        let linked = package.instantiate(&package.imports)?;
+        let sum = linked
+            .instance
+            .exports
+            .get_native_function::<(i32, i32), i32>("sum")?;
+        Ok(sum.call(1, 2)?)
+    }
+
+    #[test]
+    fn test_replace_rebuilds_dependents() -> anyhow::Result<()> {
+        let mut module_store = ModuleStore::default();
+        module_store.add("sum", WASM_SUM, false)?;
+        module_store.add("import", WASM_IMPORT, false)?;
+        assert_eq!(module_store.dependents("sum"), vec!["import".to_owned()]);
+        assert_eq!(call_div_sum(&module_store)?, 21);
+
+        // `div` exports div(i32, i32) -> i32 under another name, so it can't stand in for sum
+        let err = module_store.replace("sum", WASM_DIV, false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ModuleStoreError>(),
+            Some(ModuleStoreError::DependentRebuild { dependent, .. }) if dependent == "import"
+        ));
+        assert_eq!(call_div_sum(&module_store)?, 21);
+        assert!(module_store.get("sum@2").is_none());
+
+        module_store.replace("sum", WAT_FAKE_SUM, false)?;
+        assert_eq!(call_div_sum(&module_store)?, 101);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_rejects_modules_with_dependents() -> anyhow::Result<()> {
+        let mut module_store = ModuleStore::default();
+        module_store.add("sum", WASM_SUM, false)?;
+        module_store.add("import", WASM_IMPORT, false)?;
+
+        let err = module_store.remove("sum").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ModuleStoreError>(),
+            Some(&ModuleStoreError::HasDependents {
+                module: "sum".into(),
+                dependents: vec!["import".into()],
+            })
+        );
+
+        module_store.remove("import")?;
+        module_store.remove("sum")?;
+        assert!(!module_store.contains_key("sum"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_versions_and_aliases() -> anyhow::Result<()> {
+        let mut module_store = ModuleStore::default();
+        let first = module_store.add("sum", WASM_SUM, false)?;
+        let second = module_store.add("sum", WAT_FAKE_SUM, false)?;
+        assert_eq!((first.version, second.version), (1, 2));
+        assert_ne!(first.hash, second.hash);
+
+        // identical bytes reuse the existing version
+        let again = module_store.add("sum", WAT_FAKE_SUM, false)?;
+        assert_eq!(again.version, 2);
+
+        module_store.set_alias("sum", "stable", 1)?;
+        assert_eq!(call_sum(&module_store, "sum")?, 100);
+        assert_eq!(call_sum(&module_store, "sum:latest")?, 100);
+        assert_eq!(call_sum(&module_store, "sum:stable")?, 3);
+        assert_eq!(call_sum(&module_store, "sum@1")?, 3);
+        assert_eq!(&*module_store.get("sum@1").unwrap().data, WASM_SUM);
+        assert_eq!(&*module_store.get("sum").unwrap().data, WAT_FAKE_SUM);
+        assert!(module_store.get("sum@3").is_none());
+        assert!(module_store.get("sum:canary").is_none());
+
+        // rolling back moves latest without creating a version
+        module_store.set_alias("sum", "latest", 1)?;
+        assert_eq!(call_sum(&module_store, "sum")?, 3);
+        assert_eq!(module_store.entry("sum").unwrap().versions().count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_restores_registrations() -> anyhow::Result<()> {
+        let storage = Arc::new(MemoryStorage::default());
+        let mut module_store = ModuleStore::open(ModuleCompiler::default(), storage.clone())?;
+        module_store.add("sum", WASM_SUM, false)?;
+        module_store.add("import", WASM_IMPORT, false)?;
+        module_store.add("sum", WAT_FAKE_SUM, false)?;
+        module_store.set_alias("sum", "stable", 1)?;
+
+        let module_store = ModuleStore::open(ModuleCompiler::default(), storage)?;
+        assert_eq!(call_sum(&module_store, "sum:stable")?, 3);
+        assert_eq!(call_div_sum(&module_store)?, 101);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_fails_on_versions_it_cannot_restore() -> anyhow::Result<()> {
+        let storage = Arc::new(MemoryStorage::default());
+        let mut module_store = ModuleStore::open(ModuleCompiler::default(), storage.clone())?;
+        module_store.add("sum", WASM_SUM, false)?;
+
+        let stored = storage.load()?;
+        let (record, _) = &stored[0].versions[0];
+        storage.save_version("sum", record, b"not wasm")?;
+
+        let err = ModuleStore::open(ModuleCompiler::default(), storage)
+            .err()
+            .expect("the broken version fails the restore");
+        assert!(err.to_string().contains("sum@1"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_registrations_are_not_persisted() -> anyhow::Result<()> {
+        let storage = Arc::new(MemoryStorage::default());
+        let mut module_store = ModuleStore::open(ModuleCompiler::default(), storage.clone())?;
+        module_store.add("sum", WASM_SUM, false)?;
+        module_store.add("import", WASM_IMPORT, false)?;
+
+        assert!(module_store.replace("sum", WASM_DIV, false).is_err());
+        module_store.replace("sum", WAT_FAKE_SUM, false)?;
+
+        let stored = storage.load()?;
+        let sum = stored.iter().find(|module| module.name == "sum").unwrap();
+        let versions = sum
+            .versions
+            .iter()
+            .map(|(record, _)| record.version)
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec![1, 2]);
+
+        let module_store = ModuleStore::open(ModuleCompiler::default(), storage)?;
+        assert_eq!(call_div_sum(&module_store)?, 101);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reused_instances_need_resettable_dependencies() -> anyhow::Result<()> {
+        let exported = br#"(module
+            (global $count (export "count") (mut i32) (i32.const 0))
+            (func (export "bump") (result i32)
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (global.get $count)))"#;
+        let hidden = br#"(module
+            (global $count (mut i32) (i32.const 0))
+            (func (export "bump") (result i32)
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (global.get $count)))"#;
+        let caller = br#"(module
+            (import "counter" "bump" (func $bump (result i32)))
+            (func (export "bump") (result i32) (call $bump)))"#;
+        let reuse = ModuleConfig {
+            instance_policy: InstancePolicy::Reuse,
+            ..Default::default()
+        };
+
+        let mut module_store = ModuleStore::default();
+        module_store.add("counter", hidden, false)?;
+        let err = module_store
+            .add_with_config("caller", caller, false, reuse.clone())
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ModuleStoreError::CannotReuseInstances { .. })
+        ));
+
+        // A dependency replaced by one that can't be reset breaks the dependents reusing it.
+        module_store.replace("counter", exported, false)?;
+        module_store.add_with_config("caller", caller, false, reuse)?;
+        let err = module_store.replace("counter", hidden, false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ModuleStoreError::DependentRebuild { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuilt_dependents_keep_their_instance_count() -> anyhow::Result<()> {
+        let mut module_store = ModuleStore::default();
+        module_store.add("sum", WASM_SUM, false)?;
+        module_store.add("import", WASM_IMPORT, false)?;
+
+        let slot = module_store
+            .get("import")
+            .unwrap()
+            .instances
+            .acquire(None)?;
+        module_store.replace("sum", WAT_FAKE_SUM, false)?;
+        let rebuilt = module_store.get("import").unwrap();
+        assert_eq!(rebuilt.instances.running(), 1);
+        drop(slot);
+        assert_eq!(rebuilt.instances.running(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_forked_stores_are_independent() -> anyhow::Result<()> {
+        let storage = Arc::new(MemoryStorage::default());
+        let mut module_store = ModuleStore::open(ModuleCompiler::default(), storage.clone())?;
+        module_store.add("sum", WASM_SUM, false)?;
+
+        let mut fork = module_store.fork()?;
+        fork.add("sum", WAT_FAKE_SUM, false)?;
+        fork.remove("sum")?;
+        assert_eq!(call_sum(&module_store, "sum")?, 3);
+
+        let module_store = ModuleStore::open(ModuleCompiler::default(), storage)?;
+        assert_eq!(call_sum(&module_store, "sum")?, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_store_publishes_successful_updates() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let shared = SharedModuleStore::new(ModuleStore::default());
+        let before = shared.snapshot();
+
+        runtime.block_on(shared.update(|store| store.add("sum", WASM_SUM, false)))?;
+        assert!(!before.contains_key("sum"));
+        assert_eq!(call_sum(&shared.snapshot(), "sum")?, 3);
+
+        let err = runtime.block_on(shared.update(|store| store.add("broken", b"not wasm", false)));
+        assert!(err.is_err());
+        assert!(!shared.snapshot().contains_key("broken"));
+
+        // an update keeps going after its caller gives up on it, and the next one waits for it
+        let slow = shared.update(|store| {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            store.add("slow", WASM_SUM, false)
+        });
+        let abandoned = runtime.block_on(async {
+            tokio::time::timeout(std::time::Duration::from_millis(5), slow).await
+        });
+        assert!(abandoned.is_err());
+        runtime.block_on(shared.update(|store| store.add("next", WASM_DIV, false)))?;
+        let snapshot = shared.snapshot();
+        assert!(snapshot.contains_key("slow") && snapshot.contains_key("next"));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,558 @@
+use anyhow::{anyhow, bail, Context};
+use serde_json::{json, Map, Value as Json};
+use wasmer::{Type, Value};
+
+use crate::runtime::{
+    guest_memory::{GuestMemory, GuestMemoryError},
+    values::{parse_json_number, to_json, MAX_SAFE_INTEGER},
+    wit::{align_to, WitFunction, WitType},
+};
+
+/// Lowers JSON arguments into the core values of `function`, copying strings, lists and
+/// params that don't fit the flat limit into guest memory. The callee owns what is
+/// allocated for it.
+pub fn lower_args(
+    memory: &GuestMemory,
+    function: &WitFunction,
+    args: &[Json],
+) -> anyhow::Result<Vec<Value>> {
+    if args.len() != function.params.len() {
+        bail!(
+            "{} takes {} arguments, got {}",
+            function.name,
+            function.params.len(),
+            args.len()
+        );
+    }
+
+    let mut flat = Vec::new();
+    if function.params_in_memory() {
+        let tuple = function.params_tuple();
+        let ptr = memory.alloc(tuple.size(), tuple.align())?;
+        store(memory, &tuple, &Json::Array(args.to_vec()), ptr)?;
+        flat.push(Value::I32(ptr));
+    } else {
+        for ((name, ty), arg) in function.params.iter().zip(args) {
+            lower(memory, ty, arg, &mut flat).with_context(|| format!("argument {}", name))?;
+        }
+    }
+
+    Ok(flat)
+}
+
+/// Lifts the core results of `function` into JSON, freeing the strings and lists the callee
+/// handed over.
+pub fn lift_result(
+    memory: &GuestMemory,
+    function: &WitFunction,
+    results: &[Value],
+) -> anyhow::Result<Option<Json>> {
+    let ty = match &function.result {
+        Some(ty) => ty,
+        None => return Ok(None),
+    };
+
+    let mut owned = Vec::new();
+    let value = match function.results_in_memory() {
+        true => {
+            let area = results
+                .first()
+                .and_then(Value::i32)
+                .ok_or_else(|| anyhow!("{} did not return its result area", function.name))?;
+            load(memory, ty, area, &mut owned)?
+        }
+        false => lift(memory, ty, &mut results.iter().cloned(), &mut owned)?,
+    };
+
+    for (ptr, size, align) in owned {
+        memory.free_aligned(ptr, size, align)?;
+    }
+
+    Ok(Some(value))
+}
+
+fn lower(
+    memory: &GuestMemory,
+    ty: &WitType,
+    value: &Json,
+    flat: &mut Vec<Value>,
+) -> anyhow::Result<()> {
+    match ty {
+        WitType::String | WitType::List(_) => {
+            let (ptr, len) = store_buffer(memory, ty, value)?;
+            flat.extend([Value::I32(ptr), Value::I32(len)]);
+        }
+        WitType::Tuple(_) | WitType::Record(_) => {
+            for (field, value) in ty.fields().zip(fields(ty, value)?) {
+                lower(memory, field, value, flat)?;
+            }
+        }
+        WitType::Variant(cases) => {
+            let (discriminant, payload) = case(cases, value)?;
+            let mut slots = Vec::new();
+            ty.flatten(&mut slots);
+            flat.push(Value::I32(discriminant as i32));
+
+            let mut lowered = Vec::new();
+            if let (Some(ty), Some(value)) = (&cases[discriminant].1, payload) {
+                lower(memory, ty, value, &mut lowered)?;
+            }
+            let mut lowered = lowered.into_iter();
+            for slot in &slots[1..] {
+                flat.push(match lowered.next() {
+                    Some(value) => widen(value, *slot),
+                    None => zero(*slot),
+                });
+            }
+        }
+        WitType::Enum(cases) => flat.push(Value::I32(enum_case(cases, value)? as i32)),
+        _ => flat.push(scalar(ty, value)?),
+    }
+
+    Ok(())
+}
+
+fn lift(
+    memory: &GuestMemory,
+    ty: &WitType,
+    flat: &mut dyn Iterator<Item = Value>,
+    owned: &mut Vec<(i32, i32, usize)>,
+) -> anyhow::Result<Json> {
+    let mut next = || flat.next().ok_or_else(|| anyhow!("missing core result"));
+
+    Ok(match ty {
+        WitType::String | WitType::List(_) => {
+            let ptr = next()?.unwrap_i32();
+            let len = next()?.unwrap_i32();
+            load_buffer(memory, ty, ptr, len, owned)?
+        }
+        WitType::Tuple(_) => Json::Array(
+            ty.fields()
+                .map(|field| lift(memory, field, flat, owned))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        WitType::Record(fields) => Json::Object(
+            fields
+                .iter()
+                .map(|(name, field)| Ok((name.clone(), lift(memory, field, flat, owned)?)))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        WitType::Variant(cases) => {
+            let discriminant = next()?.unwrap_i32() as usize;
+            let mut slots = Vec::new();
+            ty.flatten(&mut slots);
+            let payload = slots[1..]
+                .iter()
+                .map(|_| next())
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let (name, case) = cases
+                .get(discriminant)
+                .ok_or_else(|| anyhow!("invalid variant discriminant {}", discriminant))?;
+            match case {
+                Some(case) => {
+                    let mut types = Vec::new();
+                    case.flatten(&mut types);
+                    let mut narrowed = payload
+                        .into_iter()
+                        .zip(types)
+                        .map(|(value, ty)| narrow(value, ty));
+                    json!({ name: lift(memory, case, &mut narrowed, owned)? })
+                }
+                None => Json::String(name.clone()),
+            }
+        }
+        WitType::Enum(cases) => {
+            let discriminant = next()?.unwrap_i32() as usize;
+            Json::String(
+                cases
+                    .get(discriminant)
+                    .ok_or_else(|| anyhow!("invalid enum discriminant {}", discriminant))?
+                    .clone(),
+            )
+        }
+        _ => lift_scalar(ty, next()?)?,
+    })
+}
+
+/// Writes `value` to guest memory at `ptr`, laid out as `ty`.
+fn store(memory: &GuestMemory, ty: &WitType, value: &Json, ptr: i32) -> anyhow::Result<()> {
+    match ty {
+        WitType::String | WitType::List(_) => {
+            let (buffer, len) = store_buffer(memory, ty, value)?;
+            let mut pair = buffer.to_le_bytes().to_vec();
+            pair.extend(len.to_le_bytes());
+            memory.write_at(ptr, &pair)?;
+        }
+        WitType::Tuple(_) | WitType::Record(_) => {
+            let mut offset = 0;
+            for (field, value) in ty.fields().zip(fields(ty, value)?) {
+                offset = align_to(offset, field.align());
+                store(memory, field, value, offset_ptr(ptr, offset)?)?;
+                offset += field.size();
+            }
+        }
+        WitType::Variant(cases) => {
+            let (discriminant, payload) = case(cases, value)?;
+            store_discriminant(memory, cases.len(), discriminant, ptr)?;
+            if let (Some(case), Some(value)) = (&cases[discriminant].1, payload) {
+                store(memory, case, value, offset_ptr(ptr, ty.payload_offset())?)?;
+            }
+        }
+        WitType::Enum(cases) => {
+            store_discriminant(memory, cases.len(), enum_case(cases, value)?, ptr)?
+        }
+        _ => {
+            let bytes = match scalar(ty, value)? {
+                Value::I32(value) => value.to_le_bytes()[..ty.size()].to_vec(),
+                Value::I64(value) => value.to_le_bytes().to_vec(),
+                Value::F32(value) => value.to_le_bytes().to_vec(),
+                Value::F64(value) => value.to_le_bytes().to_vec(),
+                _ => unreachable!("scalars are numbers"),
+            };
+            memory.write_at(ptr, &bytes)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a value laid out as `ty` from guest memory at `ptr`.
+fn load(
+    memory: &GuestMemory,
+    ty: &WitType,
+    ptr: i32,
+    owned: &mut Vec<(i32, i32, usize)>,
+) -> anyhow::Result<Json> {
+    Ok(match ty {
+        WitType::String | WitType::List(_) => {
+            let (buffer, len) = memory.read_pair(ptr)?;
+            load_buffer(memory, ty, buffer, len, owned)?
+        }
+        WitType::Tuple(_) | WitType::Record(_) => {
+            let mut offset = 0;
+            let mut values = Vec::new();
+            for field in ty.fields() {
+                offset = align_to(offset, field.align());
+                values.push(load(memory, field, offset_ptr(ptr, offset)?, owned)?);
+                offset += field.size();
+            }
+            match ty {
+                WitType::Record(fields) => Json::Object(
+                    fields
+                        .iter()
+                        .map(|(name, _)| name.clone())
+                        .zip(values)
+                        .collect::<Map<_, _>>(),
+                ),
+                _ => Json::Array(values),
+            }
+        }
+        WitType::Variant(cases) => {
+            let discriminant = load_discriminant(memory, cases.len(), ptr)?;
+            let (name, case) = cases
+                .get(discriminant)
+                .ok_or_else(|| anyhow!("invalid variant discriminant {}", discriminant))?;
+            match case {
+                Some(case) => {
+                    let payload = offset_ptr(ptr, ty.payload_offset())?;
+                    json!({ name: load(memory, case, payload, owned)? })
+                }
+                None => Json::String(name.clone()),
+            }
+        }
+        WitType::Enum(cases) => {
+            let discriminant = load_discriminant(memory, cases.len(), ptr)?;
+            Json::String(
+                cases
+                    .get(discriminant)
+                    .ok_or_else(|| anyhow!("invalid enum discriminant {}", discriminant))?
+                    .clone(),
+            )
+        }
+        _ => {
+            let bytes = memory.read(ptr, ty.size() as i32)?;
+            let mut word = [0; 8];
+            word[..bytes.len()].copy_from_slice(&bytes);
+            let bits = u64::from_le_bytes(word);
+            let value = match ty {
+                WitType::S8 => Value::I32(bits as u8 as i8 as i32),
+                WitType::S16 => Value::I32(bits as u16 as i16 as i32),
+                WitType::U64 | WitType::S64 => Value::I64(bits as i64),
+                WitType::Float32 => Value::F32(f32::from_bits(bits as u32)),
+                WitType::Float64 => Value::F64(f64::from_bits(bits)),
+                _ => Value::I32(bits as u32 as i32),
+            };
+            lift_scalar(ty, value)?
+        }
+    })
+}
+
+/// Copies a string or list into a buffer of its own, returning its pointer and length.
+fn store_buffer(memory: &GuestMemory, ty: &WitType, value: &Json) -> anyhow::Result<(i32, i32)> {
+    match ty {
+        WitType::String => {
+            let string = value
+                .as_str()
+                .ok_or_else(|| anyhow!("expected a string, found {}", value))?;
+            memory.write(string.as_bytes())
+        }
+        WitType::List(element) => {
+            let elements = value
+                .as_array()
+                .ok_or_else(|| anyhow!("expected an array, found {}", value))?;
+            let ptr = memory.alloc(element.size() * elements.len(), element.align())?;
+            for (index, value) in elements.iter().enumerate() {
+                store(memory, element, value, element_ptr(ptr, index, element)?)
+                    .with_context(|| format!("element {}", index))?;
+            }
+            Ok((ptr, elements.len() as i32))
+        }
+        _ => unreachable!("only strings and lists have buffers"),
+    }
+}
+
+fn load_buffer(
+    memory: &GuestMemory,
+    ty: &WitType,
+    ptr: i32,
+    len: i32,
+    owned: &mut Vec<(i32, i32, usize)>,
+) -> anyhow::Result<Json> {
+    let value = match ty {
+        WitType::String => {
+            owned.push((ptr, len, 1));
+            let bytes = memory.read(ptr, len)?;
+            Json::String(String::from_utf8(bytes).context("string result is not valid UTF-8")?)
+        }
+        WitType::List(element) => {
+            let size = (len as u32).checked_mul(element.size() as u32).ok_or(
+                GuestMemoryError::OutOfBounds {
+                    ptr: ptr as u32,
+                    len: u32::MAX,
+                },
+            )?;
+            // Before building any values, so a bogus length can't make the host loop for long.
+            memory.check_bounds(ptr, size)?;
+            owned.push((ptr, size as i32, element.align()));
+            Json::Array(
+                (0..len as u32 as usize)
+                    .map(|index| load(memory, element, element_ptr(ptr, index, element)?, owned))
+                    .collect::<anyhow::Result<_>>()?,
+            )
+        }
+        _ => unreachable!("only strings and lists have buffers"),
+    };
+
+    Ok(value)
+}
+
+/// `ptr + offset` in the guest's 32 bit address space. Pointers come from the guest, so
+/// this must not wrap around to memory the value doesn't live in.
+fn offset_ptr(ptr: i32, offset: usize) -> Result<i32, GuestMemoryError> {
+    u32::try_from(offset)
+        .ok()
+        .and_then(|offset| (ptr as u32).checked_add(offset))
+        .map(|ptr| ptr as i32)
+        .ok_or(GuestMemoryError::OutOfBounds {
+            ptr: ptr as u32,
+            len: offset as u32,
+        })
+}
+
+/// The address of element `index` of a list stored at `ptr`.
+fn element_ptr(ptr: i32, index: usize, element: &WitType) -> Result<i32, GuestMemoryError> {
+    index.checked_mul(element.size()).map_or(
+        Err(GuestMemoryError::OutOfBounds {
+            ptr: ptr as u32,
+            len: u32::MAX,
+        }),
+        |offset| offset_ptr(ptr, offset),
+    )
+}
+
+fn store_discriminant(
+    memory: &GuestMemory,
+    cases: usize,
+    discriminant: usize,
+    ptr: i32,
+) -> anyhow::Result<()> {
+    let size = crate::runtime::wit::discriminant_size(cases);
+    memory.write_at(ptr, &(discriminant as u32).to_le_bytes()[..size])?;
+    Ok(())
+}
+
+fn load_discriminant(memory: &GuestMemory, cases: usize, ptr: i32) -> anyhow::Result<usize> {
+    let size = crate::runtime::wit::discriminant_size(cases);
+    let bytes = memory.read(ptr, size as i32)?;
+    let mut word = [0; 4];
+    word[..size].copy_from_slice(&bytes);
+    Ok(u32::from_le_bytes(word) as usize)
+}
+
+/// The values of a tuple (a JSON array) or record (a JSON object keyed by field name).
+fn fields<'v>(ty: &WitType, value: &'v Json) -> anyhow::Result<Vec<&'v Json>> {
+    match (ty, value) {
+        (WitType::Tuple(types), Json::Array(values)) if types.len() == values.len() => {
+            Ok(values.iter().collect())
+        }
+        (WitType::Record(fields), Json::Object(values)) => fields
+            .iter()
+            .map(|(name, _)| {
+                values
+                    .get(name)
+                    .ok_or_else(|| anyhow!("missing field {}", name))
+            })
+            .collect(),
+        _ => bail!("expected a {}, found {}", describe(ty), value),
+    }
+}
+
+/// Variant cases are written `"case"` without a payload and `{ "case": payload }` with one.
+fn case<'v>(
+    cases: &[(String, Option<WitType>)],
+    value: &'v Json,
+) -> anyhow::Result<(usize, Option<&'v Json>)> {
+    let (name, payload) = match value {
+        Json::String(name) => (name, None),
+        Json::Object(object) if object.len() == 1 => {
+            let (name, payload) = object.iter().next().unwrap();
+            (name, Some(payload))
+        }
+        // `option<T>` also takes `null` or the bare value.
+        Json::Null if is_option(cases) => return Ok((0, None)),
+        value if is_option(cases) => return Ok((1, Some(value))),
+        _ => bail!("expected a variant case, found {}", value),
+    };
+
+    let discriminant = cases
+        .iter()
+        .position(|(case, _)| case == name)
+        .ok_or_else(|| anyhow!("unknown variant case {}", name))?;
+    match (&cases[discriminant].1, payload) {
+        (Some(_), None) => bail!("case {} needs a payload", name),
+        (None, Some(_)) => bail!("case {} has no payload", name),
+        _ => Ok((discriminant, payload)),
+    }
+}
+
+fn is_option(cases: &[(String, Option<WitType>)]) -> bool {
+    matches!(cases, [(none, None), (some, Some(_))] if none == "none" && some == "some")
+}
+
+fn enum_case(cases: &[String], value: &Json) -> anyhow::Result<usize> {
+    value
+        .as_str()
+        .and_then(|name| cases.iter().position(|case| case == name))
+        .ok_or_else(|| anyhow!("expected one of {}, found {}", cases.join(", "), value))
+}
+
+fn scalar(ty: &WitType, value: &Json) -> anyhow::Result<Value> {
+    let mismatch = || anyhow!("expected {}, found {}", describe(ty), value);
+    let int = |min: i64, max: i64| {
+        value
+            .as_i64()
+            .filter(|int| (min..=max).contains(int))
+            .ok_or_else(mismatch)
+    };
+
+    Ok(match ty {
+        WitType::Bool => Value::I32(value.as_bool().ok_or_else(mismatch)? as i32),
+        WitType::U8 => Value::I32(int(0, u8::MAX.into())? as i32),
+        WitType::U16 => Value::I32(int(0, u16::MAX.into())? as i32),
+        WitType::U32 => Value::I32(int(0, u32::MAX.into())? as u32 as i32),
+        WitType::S8 => Value::I32(int(i8::MIN.into(), i8::MAX.into())? as i32),
+        WitType::S16 => Value::I32(int(i16::MIN.into(), i16::MAX.into())? as i32),
+        WitType::S32 => Value::I32(int(i32::MIN.into(), i32::MAX.into())? as i32),
+        // Like results, 64 bit integers and special floats may be passed as text.
+        WitType::U64 => Value::I64(
+            value
+                .as_u64()
+                .or_else(|| value.as_str()?.trim().parse().ok())
+                .ok_or_else(mismatch)? as i64,
+        ),
+        WitType::S64 => parse_json_number(Type::I64, value).map_err(|_| mismatch())?,
+        WitType::Float32 => parse_json_number(Type::F32, value).map_err(|_| mismatch())?,
+        WitType::Float64 => parse_json_number(Type::F64, value).map_err(|_| mismatch())?,
+        WitType::Char => {
+            let mut chars = value.as_str().ok_or_else(mismatch)?.chars();
+            match (chars.next(), chars.next()) {
+                (Some(char), None) => Value::I32(char as i32),
+                _ => return Err(mismatch()),
+            }
+        }
+        _ => unreachable!("not a scalar"),
+    })
+}
+
+fn lift_scalar(ty: &WitType, value: Value) -> anyhow::Result<Json> {
+    let int = || {
+        value
+            .i32()
+            .ok_or_else(|| anyhow!("expected an i32, found {:?}", value))
+    };
+
+    Ok(match ty {
+        WitType::Bool => Json::Bool(int()? != 0),
+        WitType::U8 => json!(int()? as u8),
+        WitType::U16 => json!(int()? as u16),
+        WitType::U32 => json!(int()? as u32),
+        WitType::S8 => json!(int()? as i8),
+        WitType::S16 => json!(int()? as i16),
+        WitType::S32 => json!(int()?),
+        // Same rules as plain results: exact integers and special floats as text.
+        WitType::U64 => match value.unwrap_i64() as u64 {
+            int if int <= MAX_SAFE_INTEGER as u64 => json!(int),
+            int => Json::String(int.to_string()),
+        },
+        WitType::S64 | WitType::Float32 | WitType::Float64 => to_json(&value, false),
+        WitType::Char => {
+            let char = char::from_u32(int()? as u32)
+                .ok_or_else(|| anyhow!("{:?} is not a char", value))?;
+            Json::String(char.to_string())
+        }
+        _ => unreachable!("not a scalar"),
+    })
+}
+
+/// Stores a case's payload value in the joined slot of the variant.
+fn widen(value: Value, slot: Type) -> Value {
+    match (value, slot) {
+        (Value::F32(value), Type::I32) => Value::I32(value.to_bits() as i32),
+        (Value::I32(value), Type::I64) => Value::I64(value as u32 as i64),
+        (Value::F32(value), Type::I64) => Value::I64(value.to_bits() as i64),
+        (Value::F64(value), Type::I64) => Value::I64(value.to_bits() as i64),
+        (value, _) => value,
+    }
+}
+
+/// Reads a case's payload value back out of the joined slot.
+fn narrow(value: Value, ty: Type) -> Value {
+    match (value, ty) {
+        (Value::I32(bits), Type::F32) => Value::F32(f32::from_bits(bits as u32)),
+        (Value::I64(bits), Type::I32) => Value::I32(bits as i32),
+        (Value::I64(bits), Type::F32) => Value::F32(f32::from_bits(bits as u32)),
+        (Value::I64(bits), Type::F64) => Value::F64(f64::from_bits(bits as u64)),
+        (value, _) => value,
+    }
+}
+
+fn zero(ty: Type) -> Value {
+    match ty {
+        Type::I64 => Value::I64(0),
+        Type::F32 => Value::F32(0.0),
+        Type::F64 => Value::F64(0.0),
+        _ => Value::I32(0),
+    }
+}
+
+fn describe(ty: &WitType) -> String {
+    match ty {
+        WitType::List(_) => "list".to_owned(),
+        WitType::Tuple(types) => format!("tuple of {}", types.len()),
+        WitType::Record(_) => "record".to_owned(),
+        WitType::Variant(_) => "variant".to_owned(),
+        WitType::Enum(_) => "enum".to_owned(),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
@@ -1,12 +1,78 @@
-use crate::module_store::ModulePackage;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    module_store::{ModulePackage, ModuleRef},
+    runtime::{
+        canonical_abi::{lift_result, lower_args},
+        guest_memory::GuestMemory,
+        instance_pool::InstanceLease,
+        output::{CapturedOutput, OutputChunk, OutputStream, DEFAULT_OUTPUT_LIMIT},
+        signature::{check_args, infer_args, positional},
+        values::{funcref_name, parse_json_value, to_json},
+        wit::WitFunction,
+    },
+};
+use anyhow::Context;
+use parking_lot::{Condvar, Mutex};
 use serde::{Deserialize, Serialize};
-use wasmer::Instance;
+use tokio::sync::mpsc::UnboundedSender;
+use wasmer::{ImportObject, Instance};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use wasmer_wasi::{Pipe, WasiEnv, WasiError, WasiState};
+
+/// Type of an invocation argument or result. Strings and bytes are copied through guest
+/// memory and passed as an `i32` pointer and length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WasmType {
+    I32,
+    I64,
+    F32,
+    F64,
+    V128,
+    ExternRef,
+    FuncRef,
+    /// UTF-8 text.
+    String,
+    /// Base64 encoded in requests and responses.
+    Bytes,
+}
+
+impl WasmType {
+    pub fn in_memory(self) -> bool {
+        matches!(self, WasmType::String | WasmType::Bytes)
+    }
+}
+
+impl From<wasmer::Type> for WasmType {
+    fn from(ty: wasmer::Type) -> Self {
+        match ty {
+            wasmer::Type::I32 => WasmType::I32,
+            wasmer::Type::I64 => WasmType::I64,
+            wasmer::Type::F32 => WasmType::F32,
+            wasmer::Type::F64 => WasmType::F64,
+            wasmer::Type::V128 => WasmType::V128,
+            wasmer::Type::ExternRef => WasmType::ExternRef,
+            wasmer::Type::FuncRef => WasmType::FuncRef,
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct WasmArg {
-    pub value: String,
-    pub arg_type: wasmer::Type,
+    /// A JSON number, or text as read by [`parse_value`](crate::runtime::values::parse_value).
+    /// Strings and bytes are always text.
+    pub value: serde_json::Value,
+    pub arg_type: WasmType,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -14,6 +80,37 @@ pub struct WasmArg {
 pub struct WasmFunction {
     pub name: String,
     pub args: Vec<WasmArg>,
+    /// How to read the results. Each string or bytes result takes a pointer and a length, or
+    /// a single pointer to both when it is the only one. Plain values when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub results: Vec<WasmType>,
+    /// Arguments typed by the function signature, used instead of `args`.
+    #[serde(default, skip_serializing_if = "ArgValues::is_empty")]
+    pub values: ArgValues,
+}
+
+/// Arguments coerced to the param types of the function: a positional array, or an object
+/// keyed by param name for functions of the module's WIT interface.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ArgValues {
+    Positional(Vec<serde_json::Value>),
+    Named(serde_json::Map<String, serde_json::Value>),
+}
+
+impl Default for ArgValues {
+    fn default() -> Self {
+        ArgValues::Positional(vec![])
+    }
+}
+
+impl ArgValues {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            ArgValues::Positional(values) => values.is_empty(),
+            ArgValues::Named(values) => values.is_empty(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,72 +122,857 @@ pub struct WasmImport {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ExecuteModuleRequest {
+    /// `name` for the latest version, `name@version` or `name:alias` for a specific one.
     pub module_name: String,
     pub function: WasmFunction,
+    #[serde(flatten)]
+    pub options: InvocationOptions,
+}
+
+impl ExecuteModuleRequest {
+    /// Runs a WASI command module through its [`COMMAND_ENTRY`] instead of a named export.
+    pub fn command(module_name: String, options: InvocationOptions) -> Self {
+        Self {
+            module_name,
+            function: WasmFunction {
+                name: COMMAND_ENTRY.to_owned(),
+                args: vec![],
+                results: vec![],
+                values: Default::default(),
+            },
+            options,
+        }
+    }
+}
+
+/// Per-invocation settings, overriding the defaults the module was registered with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvocationOptions {
+    /// Overrides the module's default fuel limit for this invocation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<u64>,
+    /// Wall-clock deadline in milliseconds, [`DEFAULT_TIMEOUT`] when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// WASI arguments following the program name, replacing the module's default ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argv: Option<Vec<String>>,
+    /// WASI environment variables, added to the module's defaults.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Base64 encoded WASI stdin, empty when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    /// Return floats as their bit pattern instead of JSON numbers.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub raw_bits: bool,
 }
 
+/// Entry point of WASI command modules. Calls to it report an exit code, and a
+/// `proc_exit` from the guest ends the call with that code instead of failing it.
+pub const COMMAND_ENTRY: &str = "_start";
+
+/// Deadline for invocations that don't set one.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before a timed out call is asked to stop again, doubling up to
+/// [`MAX_INTERRUPT_INTERVAL`] while it keeps running.
+const INTERRUPT_INTERVAL: Duration = Duration::from_millis(1);
+
+const MAX_INTERRUPT_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct WasmResult {
-    pub result: String,
-    pub result_type: wasmer::ValType,
+    /// See [`to_json`] for how each type is written.
+    pub result: serde_json::Value,
+    pub result_type: WasmType,
+}
+
+/// A value returned by an invocation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WasmValue {
+    Value(wasmer::Value),
+    String(String),
+    Bytes(Vec<u8>),
+    /// A function reference, by the name the instance exports it under. `None` for null.
+    FuncRef(Option<String>),
+}
+
+impl WasmResult {
+    pub fn new(value: WasmValue, raw_bits: bool) -> Self {
+        match value {
+            WasmValue::Value(value) => WasmResult {
+                result_type: value.ty().into(),
+                result: to_json(&value, raw_bits),
+            },
+            WasmValue::String(string) => WasmResult {
+                result: string.into(),
+                result_type: WasmType::String,
+            },
+            WasmValue::Bytes(bytes) => WasmResult {
+                result: base64::encode(bytes).into(),
+                result_type: WasmType::Bytes,
+            },
+            WasmValue::FuncRef(name) => WasmResult {
+                result: name.into(),
+                result_type: WasmType::FuncRef,
+            },
+        }
+    }
+}
+
+impl From<WasmValue> for WasmResult {
+    fn from(value: WasmValue) -> Self {
+        Self::new(value, false)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteModuleResponse {
+    /// One entry per value the function returns, in order: empty for functions that return
+    /// nothing and several for multi-value returns.
+    pub results: Vec<WasmResult>,
+    pub fuel_consumed: u64,
+    pub warm_start: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    /// Set when stdout or stderr went over the module's output limit and was cut short.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub output_truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<u32>,
+    /// Result of a WIT function, lifted to JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+}
+
+#[derive(Debug)]
+pub struct ExecutionOutput {
+    pub results: Vec<WasmValue>,
+    pub fuel_consumed: u64,
+    /// Whether the call ran on a pooled instance instead of instantiating the module.
+    pub warm_start: bool,
+    /// Captured WASI output, `None` for modules registered without WASI.
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub output_truncated: bool,
+    /// Exit code of a command invocation, see [`COMMAND_ENTRY`].
+    pub exit_code: Option<u32>,
+    /// Result of a function from the module's WIT interface, which has no `results`.
+    pub value: Option<serde_json::Value>,
+}
+
+impl From<ExecutionOutput> for ExecuteModuleResponse {
+    fn from(output: ExecutionOutput) -> Self {
+        Self::new(output, false)
+    }
+}
+
+impl ExecuteModuleResponse {
+    /// See [`InvocationOptions::raw_bits`].
+    pub fn new(output: ExecutionOutput, raw_bits: bool) -> Self {
+        let results = output
+            .results
+            .into_iter()
+            .map(|value| WasmResult::new(value, raw_bits))
+            .collect();
+
+        Self {
+            results,
+            fuel_consumed: output.fuel_consumed,
+            warm_start: output.warm_start,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            output_truncated: output.output_truncated,
+            exit_code: output.exit_code,
+            value: output.value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionError {
+    /// The invocation executed more instructions than its fuel limit allows.
+    OutOfFuel { limit: u64 },
+    /// The invocation did not finish before its deadline.
+    Timeout { timeout_ms: u64 },
+    /// The invocation sets argv, env or stdin for a module without WASI.
+    WasiOnly { option: &'static str },
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::OutOfFuel { limit } => {
+                write!(f, "out of fuel: exhausted the limit of {}", limit)
+            }
+            ExecutionError::Timeout { timeout_ms } => {
+                write!(f, "execution timed out after {}ms", timeout_ms)
+            }
+            ExecutionError::WasiOnly { option } => {
+                write!(f, "{} is only available to WASI modules", option)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// The running instances of an invocation, so it can be interrupted from another thread.
+#[derive(Default)]
+struct RunningInstance {
+    /// The leased instance and its dependencies, empty once the call has returned.
+    instances: Mutex<Vec<Instance>>,
+    finished: Mutex<bool>,
+    done: Condvar,
+    interrupted: AtomicBool,
+}
+
+impl RunningInstance {
+    fn finish(&self) {
+        *self.finished.lock() = true;
+        self.done.notify_all();
+    }
+
+    /// Wasm can't be preempted, but draining its fuel makes the next metering check trap.
+    /// The guest writes the counter back as it runs, so this keeps draining every instance of
+    /// the call until it returns, backing off while it doesn't. Time spent in host functions
+    /// or start functions can't be interrupted.
+    fn interrupt(self: Arc<Self>) {
+        if self.interrupted.swap(true, Ordering::AcqRel) || *self.finished.lock() {
+            return;
+        }
+        std::thread::spawn(move || {
+            let mut interval = INTERRUPT_INTERVAL;
+            let mut finished = self.finished.lock();
+            while !*finished {
+                for instance in self.instances.lock().iter() {
+                    set_remaining_points(instance, 0);
+                }
+                self.done.wait_for(&mut finished, interval);
+                interval = (interval * 2).min(MAX_INTERRUPT_INTERVAL);
+            }
+        });
+    }
+}
+
+struct InterruptOnDrop(Arc<RunningInstance>);
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        self.0.clone().interrupt();
+    }
+}
+
+/// Carries results back from the blocking pool.
+struct SendOutput(ExecutionOutput);
+
+impl SendOutput {
+    fn new(output: ExecutionOutput) -> Self {
+        debug_assert!(!output
+            .results
+            .iter()
+            .any(|value| matches!(value, WasmValue::Value(wasmer::Value::FuncRef(_)))));
+        Self(output)
+    }
 }
 
+// SAFETY: `ExecutionOutput` is only `!Send` through the `wasmer::Value`s of its results, and
+// of those only reference values aren't plain data:
+// - funcrefs point into the instance, which isn't `Send`. `call_instance` replaces every
+//   funcref result with the name it is exported under, so the output never holds one.
+// - externrefs are a pointer to a heap allocation with an atomic reference count and a
+//   `Send + Sync` payload. Clones left in the instance (tables, globals) may be dropped on
+//   another thread at the same time, which the atomic count allows.
+unsafe impl Send for SendOutput {}
+
+/// Runs the function on the blocking pool, failing with [`ExecutionError::Timeout`] and
+/// interrupting the instance once the request deadline passes.
 pub async fn execute_function(
-    module: &ModulePackage,
+    module: Arc<ModulePackage>,
+    payload: ExecuteModuleRequest,
+) -> anyhow::Result<ExecutionOutput> {
+    run(module, payload, None).await
+}
+
+/// Like [`execute_function`], also sending WASI output to `sink` as the module writes it.
+pub async fn execute_function_streaming(
+    module: Arc<ModulePackage>,
     payload: ExecuteModuleRequest,
-) -> anyhow::Result<Box<[wasmer::Value]>> {
-    let instance = Instance::new(&module.module, &module.imports)?;
+    sink: UnboundedSender<OutputChunk>,
+) -> anyhow::Result<ExecutionOutput> {
+    run(module, payload, Some(sink)).await
+}
 
-    let wasm_function = instance.exports.get_function(&payload.function.name)?;
+async fn run(
+    module: Arc<ModulePackage>,
+    payload: ExecuteModuleRequest,
+    sink: Option<UnboundedSender<OutputChunk>>,
+) -> anyhow::Result<ExecutionOutput> {
+    let timeout = payload
+        .options
+        .timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_TIMEOUT);
+    let running = Arc::new(RunningInstance::default());
 
-    let args = &payload
-        .function
-        .args
-        .into_iter()
-        .map(parse_arg)
-        .collect::<Result<Vec<_>, _>>()?;
+    // Dropping the call, like a streaming client going away does, stops it too.
+    let _interrupt = InterruptOnDrop(running.clone());
+    let mut call = tokio::task::spawn_blocking({
+        let running = running.clone();
+        move || {
+            let result = call_function(&module, payload, sink, &running);
+            running.finish();
+            result.map(SendOutput::new)
+        }
+    });
+
+    match tokio::time::timeout(timeout, &mut call).await {
+        Ok(result) => Ok(result??.0),
+        Err(_) => {
+            running.interrupt();
+            Err(ExecutionError::Timeout {
+                timeout_ms: timeout.as_millis() as u64,
+            }
+            .into())
+        }
+    }
+}
+
+fn call_function(
+    module: &ModulePackage,
+    mut payload: ExecuteModuleRequest,
+    sink: Option<UnboundedSender<OutputChunk>>,
+    running: &RunningInstance,
+) -> anyhow::Result<ExecutionOutput> {
+    let fuel_limit = payload
+        .options
+        .fuel
+        .or(module.config.fuel)
+        .unwrap_or(u64::MAX);
+
+    if !module.wasi {
+        let options = &payload.options;
+        let set = [
+            ("argv", options.argv.is_some()),
+            ("env", !options.env.is_empty()),
+            ("stdin", options.stdin.is_some()),
+        ];
+        if let Some((option, _)) = set.into_iter().find(|(_, set)| *set) {
+            return Err(ExecutionError::WasiOnly { option }.into());
+        }
+    }
+
+    // Held until the call returns, even past its deadline, so a call that can't be
+    // interrupted keeps counting against the limit.
+    let _slot = module
+        .instances
+        .acquire(module.config.limits.max_instances)?;
+    let output_limit = module
+        .config
+        .max_output_bytes
+        .unwrap_or(DEFAULT_OUTPUT_LIMIT);
+    let mut stdout = CapturedOutput::new(OutputStream::Stdout, output_limit, sink.clone());
+    let mut stderr = CapturedOutput::new(OutputStream::Stderr, output_limit, sink);
+
+    let interface = module
+        .interface
+        .as_ref()
+        .and_then(|interface| interface.function(&payload.function.name));
+    if interface.is_none() {
+        infer_args(&module.module, &mut payload.function)?;
+        check_args(&module.module, &payload.function)?;
+    }
+
+    let lease = match module.wasi {
+        true => module
+            .pool
+            .acquire(module, wasi_imports(module, &payload, &stdout, &stderr)?)?,
+        false => module.pool.acquire(module, &module.imports)?,
+    };
+    let warm_start = lease.warm;
+    for instance in lease.instances() {
+        set_remaining_points(instance, fuel_limit);
+    }
+    *running.instances.lock() = lease.instances().cloned().collect();
+
+    let result = call_instance(&lease, payload.function, interface, fuel_limit);
+
+    // Stop any interruption before the instance can be handed to another call.
+    running.instances.lock().clear();
+    module.pool.release(lease, result.is_ok());
+
+    // Streams whatever is left of a character the module didn't finish writing.
+    stdout.flush()?;
+    stderr.flush()?;
+
+    let call = result?;
+    Ok(ExecutionOutput {
+        results: call.results,
+        value: call.value,
+        fuel_consumed: call.fuel_consumed,
+        exit_code: call.exit_code,
+        warm_start,
+        stdout: module.wasi.then(|| stdout.contents()),
+        stderr: module.wasi.then(|| stderr.contents()),
+        output_truncated: stdout.truncated() || stderr.truncated(),
+    })
+}
+
+/// Gives the invocation a WASI environment of its own, writing to the captured pipes and
+/// seeing only the module's mounts.
+fn wasi_imports(
+    module: &ModulePackage,
+    payload: &ExecuteModuleRequest,
+    stdout: &CapturedOutput,
+    stderr: &CapturedOutput,
+) -> anyhow::Result<ImportObject> {
+    let options = &payload.options;
+
+    let mut stdin = Pipe::new();
+    if let Some(data) = &options.stdin {
+        let data = base64::decode(data).context("stdin is not valid base64")?;
+        stdin.write_all(&data)?;
+    }
+
+    let program = ModuleRef::parse(&payload.module_name)
+        .map_or_else(|| payload.module_name.clone(), |reference| reference.name);
+    let args = options.argv.as_ref().unwrap_or(&module.config.args);
+    let mut env = module.config.env.clone();
+    env.extend(options.env.clone());
+
+    let mut builder = WasiState::new(program);
+    builder
+        .args(args)
+        .envs(env)
+        .stdin(Box::new(stdin))
+        .stdout(Box::new(stdout.clone()))
+        .stderr(Box::new(stderr.clone()));
+    module.mounts.preopen(&mut builder)?;
+    let wasi_state = builder.build()?;
+
+    Ok(WasiEnv::new(wasi_state).import_object(&module.module)?)
+}
+
+/// What [`call_instance`] hands back to [`call_function`].
+#[derive(Default)]
+struct Call {
+    results: Vec<WasmValue>,
+    value: Option<serde_json::Value>,
+    fuel_consumed: u64,
+    exit_code: Option<u32>,
+}
+
+/// Calls `function`, lowering its JSON `values` through the canonical ABI when `interface`
+/// describes it.
+fn call_instance(
+    lease: &InstanceLease,
+    function: WasmFunction,
+    interface: Option<&WitFunction>,
+    fuel_limit: u64,
+) -> anyhow::Result<Call> {
+    let instance = &lease.instance;
+    let wasm_function = instance.exports.get_function(&function.name)?;
+    let command = function.name == COMMAND_ENTRY;
+
+    if interface.is_some() && !function.args.is_empty() {
+        anyhow::bail!(
+            "{} is described by the module interface and takes JSON values, not args",
+            function.name
+        );
+    }
+
+    let uses_memory = interface.is_some()
+        || function
+            .args
+            .iter()
+            .map(|arg| arg.arg_type)
+            .chain(function.results.iter().copied())
+            .any(WasmType::in_memory);
+    let memory = match uses_memory {
+        true => Some(GuestMemory::new(instance)?),
+        false => None,
+    };
+
+    let mut args = Vec::with_capacity(function.args.len());
+    if let (Some(interface), Some(memory)) = (interface, &memory) {
+        let names = interface
+            .params
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        let values = positional(function.values, &function.name, Some(&names))?;
+        args = lower_args(memory, interface, &values)?;
+    }
+    let mut buffers = Vec::new();
+    for arg in function.args {
+        match (arg.arg_type, &memory) {
+            (WasmType::String | WasmType::Bytes, Some(memory)) => {
+                let text = match arg.value {
+                    serde_json::Value::String(text) => text,
+                    other => {
+                        anyhow::bail!("{:?} arguments are text, found {}", arg.arg_type, other)
+                    }
+                };
+                let data = match arg.arg_type {
+                    WasmType::Bytes => {
+                        base64::decode(&text).context("bytes argument is not valid base64")?
+                    }
+                    _ => text.into_bytes(),
+                };
+                let (ptr, len) = memory.write(&data)?;
+                buffers.push((ptr, len));
+                args.extend([wasmer::Value::I32(ptr), wasmer::Value::I32(len)]);
+            }
+            _ => args.push(parse_arg(arg, instance)?),
+        }
+    }
 
-    let fn_result = wasm_function.call(args)?;
+    let fn_result = wasm_function.call(&args);
 
-    Ok(fn_result)
+    let fuel_consumed = fuel_consumed(lease, fuel_limit)?;
+
+    if command {
+        let exit_code = match fn_result {
+            Ok(_) => 0,
+            Err(err) => match err.downcast::<WasiError>() {
+                Ok(WasiError::Exit(code)) => code,
+                Ok(err) => return Err(err.into()),
+                Err(err) => return Err(err.into()),
+            },
+        };
+        return Ok(Call {
+            fuel_consumed,
+            exit_code: Some(exit_code),
+            ..Call::default()
+        });
+    }
+
+    if let (Some(interface), Some(memory)) = (interface, &memory) {
+        return Ok(Call {
+            value: lift_result(memory, interface, &fn_result?)?,
+            fuel_consumed,
+            ..Call::default()
+        });
+    }
+
+    let results = read_results(fn_result?, &function.results, memory.as_ref())?
+        .into_iter()
+        .map(|value| match value {
+            WasmValue::Value(wasmer::Value::FuncRef(function)) => {
+                WasmValue::FuncRef(funcref_name(function.as_ref(), instance))
+            }
+            value => value,
+        })
+        .collect();
+    if let Some(memory) = &memory {
+        for (ptr, len) in buffers {
+            memory.free(ptr, len)?;
+        }
+    }
+
+    Ok(Call {
+        results,
+        fuel_consumed,
+        ..Call::default()
+    })
 }
 
-fn parse_arg(arg: WasmArg) -> anyhow::Result<wasmer::Value> {
-    Ok(match arg.arg_type {
-        wasmer::ValType::I32 => wasmer::Value::I32(arg.value.parse()?),
-        wasmer::ValType::I64 => wasmer::Value::I64(arg.value.parse()?),
-        wasmer::ValType::F32 => wasmer::Value::F32(arg.value.parse()?),
-        wasmer::ValType::F64 => wasmer::Value::F64(arg.value.parse()?),
-        wasmer::ValType::V128 => todo!(),
-        wasmer::ValType::ExternRef => todo!(),
-        wasmer::ValType::FuncRef => todo!(),
+/// Fuel used by every instance of the call together. Each one was given the whole limit, so a
+/// call spread over several instances can go over it and is failed here.
+fn fuel_consumed(lease: &InstanceLease, fuel_limit: u64) -> Result<u64, ExecutionError> {
+    let out_of_fuel = ExecutionError::OutOfFuel { limit: fuel_limit };
+
+    lease.instances().try_fold(0u64, |total, instance| {
+        match get_remaining_points(instance) {
+            MeteringPoints::Remaining(remaining) => total
+                .checked_add(fuel_limit - remaining)
+                .filter(|&total| total <= fuel_limit)
+                .ok_or_else(|| out_of_fuel.clone()),
+            MeteringPoints::Exhausted => Err(out_of_fuel.clone()),
+        }
     })
 }
 
+/// Reads the raw results as `types`, copying strings and bytes out of guest memory and
+/// freeing them.
+fn read_results(
+    raw: Box<[wasmer::Value]>,
+    types: &[WasmType],
+    memory: Option<&GuestMemory>,
+) -> anyhow::Result<Vec<WasmValue>> {
+    let memory = match memory {
+        Some(memory) if !types.is_empty() => memory,
+        _ => return Ok(raw.into_vec().into_iter().map(WasmValue::Value).collect()),
+    };
+
+    let slots = types
+        .iter()
+        .map(|ty| if ty.in_memory() { 2 } else { 1 })
+        .sum::<usize>();
+    let raw = match (types, &*raw) {
+        (_, raw) if raw.len() == slots => raw.to_vec(),
+        ([ty], [wasmer::Value::I32(area)]) if ty.in_memory() => {
+            let (ptr, len) = memory.read_pair(*area)?;
+            vec![wasmer::Value::I32(ptr), wasmer::Value::I32(len)]
+        }
+        (_, raw) => anyhow::bail!("function returned {} values, expected {}", raw.len(), slots),
+    };
+
+    let mut raw = raw.into_iter();
+    let mut results = Vec::with_capacity(types.len());
+    for ty in types {
+        if !ty.in_memory() {
+            results.push(WasmValue::Value(raw.next().expect("counted above")));
+            continue;
+        }
+
+        let (ptr, len) = match (raw.next(), raw.next()) {
+            (Some(wasmer::Value::I32(ptr)), Some(wasmer::Value::I32(len))) => (ptr, len),
+            _ => anyhow::bail!("{:?} results must be an i32 pointer and length", ty),
+        };
+        let data = memory.read(ptr, len)?;
+        memory.free(ptr, len)?;
+
+        results.push(match ty {
+            WasmType::String => WasmValue::String(
+                String::from_utf8(data).context("string result is not valid UTF-8")?,
+            ),
+            _ => WasmValue::Bytes(data),
+        });
+    }
+
+    Ok(results)
+}
+
+fn parse_arg(arg: WasmArg, instance: &Instance) -> anyhow::Result<wasmer::Value> {
+    let ty = match arg.arg_type {
+        WasmType::I32 => wasmer::Type::I32,
+        WasmType::I64 => wasmer::Type::I64,
+        WasmType::F32 => wasmer::Type::F32,
+        WasmType::F64 => wasmer::Type::F64,
+        WasmType::V128 => wasmer::Type::V128,
+        WasmType::ExternRef => wasmer::Type::ExternRef,
+        WasmType::FuncRef => wasmer::Type::FuncRef,
+        WasmType::String | WasmType::Bytes => unreachable!("passed through guest memory"),
+    };
+
+    parse_json_value(ty, &arg.value, instance)
+        .with_context(|| format!("invalid {:?} argument {}", arg.arg_type, arg.value))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
 
-    use wasmer::Store;
+    use serde_json::json;
+    use wasmer::Value;
 
     use crate::{
-        compile_wasm,
+        module_store::ModuleConfig,
         module_store::ModuleStore,
-        runtime::execute_module::{execute_function, ExecuteModuleRequest, WasmArg, WasmFunction},
+        runtime::execute_module::{
+            execute_function, ArgValues, ExecuteModuleRequest, ExecuteModuleResponse,
+            ExecutionError, InvocationOptions, WasmArg, WasmFunction, WasmResult, WasmType,
+            WasmValue,
+        },
+        runtime::{
+            filesystem::Mount, guest_memory::GuestMemoryError, signature::SignatureError,
+            wit::WitError,
+        },
     };
 
     static WASM_SUM: &[u8] = include_bytes!(r#"../../../binaries/compiled/sum.wasm"#);
     static WASM_DIV: &[u8] = include_bytes!(r#"../../../binaries/compiled/div.wasm"#);
     static WASM_IMPORT: &[u8] = include_bytes!(r#"../../../binaries/compiled/import.wasm"#);
+    static WASM_HELLO_WORLD: &[u8] =
+        include_bytes!(r#"../../../binaries/rust/bin/hello_world.wasm"#);
+
+    /// Prints the file at the given path inside the first mount, returning the `path_open`
+    /// errno. Fd 3 is the virtual root, so mounts start at 4.
+    static WAT_CAT: &[u8] = br#"(module
+        (import "wasi_snapshot_preview1" "path_open"
+            (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read"
+            (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 100) "greeting.txt../secret")
+        (func (export "cat") (param $path i32) (param $len i32) (result i32)
+            (local $errno i32)
+            (local.set $errno (call $path_open (i32.const 4) (i32.const 0)
+                (local.get $path) (local.get $len) (i32.const 0)
+                (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 0)))
+            (if (i32.eqz (local.get $errno)) (then
+                (i32.store (i32.const 16) (i32.const 256))
+                (i32.store (i32.const 20) (i32.const 256))
+                (drop (call $fd_read (i32.load (i32.const 0)) (i32.const 16) (i32.const 1) (i32.const 20)))
+                (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24)))))
+            (local.get $errno)))"#;
+
+    /// Bump allocator with string functions: `upper` returns a new buffer as a pointer and
+    /// length, `greeting` a pointer to a static pointer and length pair.
+    static WAT_STRINGS: &[u8] = br#"(module
+        (memory (export "memory") 1)
+        (global $next (mut i32) (i32.const 1024))
+        (data (i32.const 16) "\20\00\00\00\05\00\00\00")
+        (data (i32.const 32) "hello")
+        (func $malloc (export "malloc") (param $size i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $size))))
+        (func (export "free") (param i32))
+        (func (export "upper") (param $ptr i32) (param $len i32) (result i32 i32)
+            (local $out i32) (local $i i32) (local $c i32)
+            (local.set $out (call $malloc (local.get $len)))
+            (block $done (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                (local.set $c (i32.load8_u (i32.add (local.get $ptr) (local.get $i))))
+                (i32.store8 (i32.add (local.get $out) (local.get $i))
+                    (select (i32.sub (local.get $c) (i32.const 32)) (local.get $c)
+                        (i32.lt_u (i32.sub (local.get $c) (i32.const 97)) (i32.const 26))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+            (local.get $out)
+            (local.get $len))
+        (func (export "len") (param i32 i32) (result i32) (local.get 1))
+        (func (export "greeting") (result i32) (i32.const 16)))"#;
+
+    /// Implements [`WIT_SHAPES`] with a bump allocator. Results that don't fit a single
+    /// value are written to a static return area.
+    static WAT_SHAPES: &[u8] = br#"(module
+        (memory (export "memory") 1)
+        (global $next (mut i32) (i32.const 1024))
+        (func (export "canonical_abi_realloc") (param i32 i32 i32) (param $size i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.and (i32.add (i32.add (global.get $next) (local.get $size))
+                (i32.const 7)) (i32.const -8))))
+        (func (export "canonical_abi_free") (param i32 i32 i32))
+        (func (export "echo") (param $ptr i32) (param $len i32) (result i32)
+            (i32.store (i32.const 8) (local.get $ptr))
+            (i32.store (i32.const 12) (local.get $len))
+            (i32.const 8))
+        (func (export "scale") (param $x i32) (param $y i32) (param $by i32) (result i32)
+            (i32.store (i32.const 16) (i32.mul (local.get $x) (local.get $by)))
+            (i32.store (i32.const 20) (i32.mul (local.get $y) (local.get $by)))
+            (i32.const 16))
+        (func (export "sum") (param $ptr i32) (param $len i32) (result i64)
+            (local $total i64)
+            (block $done (loop $next
+                (br_if $done (i32.eqz (local.get $len)))
+                (local.set $total (i64.add (local.get $total)
+                    (i64.extend_i32_s (i32.load (local.get $ptr)))))
+                (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+                (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+                (br $next)))
+            (local.get $total))
+        (func (export "kind") (param $case i32) (param i32) (param $y i32) (result i32)
+            (i32.add (i32.mul (local.get $case) (i32.const 100)) (local.get $y)))
+        (func (export "lookup") (param $ptr i32) (param $len i32) (result i32)
+            (i32.store8 (i32.const 24) (i32.ne (local.get $len) (i32.const 0)))
+            (i32.store (i32.const 28) (local.get $ptr))
+            (i32.store (i32.const 32) (local.get $len))
+            (i32.const 24))
+        (func (export "ratio") (param f32 f32) (result f32)
+            (f32.div (local.get 0) (local.get 1)))
+        (func (export "negate") (param i64) (result i64)
+            (i64.sub (i64.const 0) (local.get 0)))
+        (func (export "points") (result i32)
+            (i32.store (i32.const 40) (i32.const 0))
+            (i32.store (i32.const 44) (i32.const 0x40000000))
+            (i32.const 40)))"#;
+
+    static WIT_SHAPES: &str = "
+        echo: function(s: string) -> string
+        scale: function(p: point, by: s32) -> point
+        sum: function(values: list<s32>) -> s64
+        kind: function(s: shape) -> u32
+        lookup: function(key: string) -> option<string>
+        ratio: function(a: float32, b: float32) -> float32
+        negate: function(x: s64) -> s64
+        points: function() -> list<point>
+
+        record point { x: s32, y: s32 }
+        variant shape { circle(float32), rect(point), empty }";
+
+    /// Returns no, one and several values.
+    static WAT_RESULTS: &[u8] = br#"(module
+        (func (export "none"))
+        (func (export "one") (result f64) (f64.const 0.5))
+        (func (export "many") (result i32 i64 f32)
+            (i32.const -1) (i64.const 9007199254740993) (f32.const 1.5)))"#;
+
+    /// Passes vectors and references through.
+    static WAT_REFS: &[u8] = br#"(module
+        (func (export "double") (param i32) (result i32)
+            (i32.mul (local.get 0) (i32.const 2)))
+        (func (export "pick") (param funcref) (result funcref) (local.get 0))
+        (func (export "keep") (param externref) (result externref) (local.get 0))
+        (func (export "add") (param v128 v128) (result v128)
+            (i32x4.add (local.get 0) (local.get 1))))"#;
+
+    /// Command module that prints to stderr and exits with code 3.
+    static WAT_EXIT: &[u8] = br#"(module
+        (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 16) "failed\n")
+        (func (export "_start")
+            (i32.store (i32.const 0) (i32.const 16))
+            (i32.store (i32.const 4) (i32.const 7))
+            (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (call $proc_exit (i32.const 3))
+            unreachable))"#;
+
+    /// Writes "hello\n" to stdout and "oops\n" to stderr.
+    static WAT_GREET: &[u8] = br#"(module
+        (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 16) "hello\n")
+        (data (i32.const 32) "oops\n")
+        (func (export "greet")
+            (i32.store (i32.const 0) (i32.const 16))
+            (i32.store (i32.const 4) (i32.const 6))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (i32.store (i32.const 0) (i32.const 32))
+            (i32.store (i32.const 4) (i32.const 5))
+            (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+
+    static WAT_ECHO: &[u8] = br#"(module
+        (import "wasi_snapshot_preview1" "args_sizes_get"
+            (func $args_sizes_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "environ_sizes_get"
+            (func $environ_sizes_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read"
+            (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (func (export "echo") (result i32 i32)
+            (i32.store (i32.const 16) (i32.const 64))
+            (i32.store (i32.const 20) (i32.const 256))
+            (drop (call $fd_read (i32.const 0) (i32.const 16) (i32.const 1) (i32.const 20)))
+            (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24)))
+            (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+            (drop (call $environ_sizes_get (i32.const 8) (i32.const 12)))
+            (i32.load (i32.const 0))
+            (i32.load (i32.const 8))))"#;
 
     #[test]
     fn test_execute_function() -> anyhow::Result<()> {
-        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
 
         let mut module_store = ModuleStore::default();
-        let wasm_store = Store::default();
-        let wasm_add_one = compile_wasm(&wasm_store, WASM_SUM)?;
-        module_store.add("sum", wasm_add_one, false)?;
+        module_store.add("sum", WASM_SUM, false)?;
 
         let module_store = module_store;
         let payload = ExecuteModuleRequest {
@@ -100,40 +982,39 @@ mod tests {
                 args: vec![
                     WasmArg {
                         value: "10".into(),
-                        arg_type: wasmer::ValType::I32,
+                        arg_type: WasmType::I32,
                     },
                     WasmArg {
                         value: "10".into(),
-                        arg_type: wasmer::ValType::I32,
+                        arg_type: WasmType::I32,
                     },
                 ],
+                results: vec![],
+                values: Default::default(),
             },
+            options: Default::default(),
         };
 
         let module = module_store.get("sum").unwrap().clone();
 
         let json = serde_json::to_string_pretty(&payload)?;
         println!("{}", json);
-        let result = runtime.block_on(execute_function(&module, payload))?;
+        let result = runtime.block_on(execute_function(module.clone(), payload))?;
         println!("{:#?}", result);
         std::fs::write("tests/data/sum_request.json", json)?;
-        let result = &result[0].i32().unwrap();
-        assert_eq!(*result, 20);
+        assert_eq!(result.results[0], WasmValue::Value(Value::I32(20)));
         Ok(())
     }
 
     #[test]
     fn test_resolve_imports() -> anyhow::Result<()> {
-        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
         let mut module_store = ModuleStore::default();
-        let wasm_store = Store::default();
-        let wasm_add_one = compile_wasm(&wasm_store, WASM_SUM)?;
-        let wasm_div = compile_wasm(&wasm_store, WASM_DIV)?;
-        let wasm_import = compile_wasm(&wasm_store, WASM_IMPORT)?;
-
-        module_store.add("sum", wasm_add_one, false)?;
-        module_store.add("div", wasm_div, false)?;
-        module_store.add("import", wasm_import, false)?;
+        module_store.add("sum", WASM_SUM, false)?;
+        module_store.add("div", WASM_DIV, false)?;
+        module_store.add("import", WASM_IMPORT, false)?;
 
         let module_store = module_store;
         let payload = ExecuteModuleRequest {
@@ -143,25 +1024,742 @@ mod tests {
                 args: vec![
                     WasmArg {
                         value: "10".into(),
-                        arg_type: wasmer::ValType::I32,
+                        arg_type: WasmType::I32,
                     },
                     WasmArg {
                         value: "10".into(),
-                        arg_type: wasmer::ValType::I32,
+                        arg_type: WasmType::I32,
                     },
                 ],
+                results: vec![],
+                values: Default::default(),
             },
+            options: Default::default(),
         };
 
         let module = module_store.get("import").unwrap().clone();
 
         let json = serde_json::to_string_pretty(&payload)?;
         println!("{}", json);
-        let result = runtime.block_on(execute_function(&module, payload))?;
+        let result = runtime.block_on(execute_function(module.clone(), payload))?;
         println!("{:#?}", result);
         std::fs::write("tests/data/import_request.json", json)?;
         // let result = &result[0].i32().unwrap();
         // assert_eq!(*result, 20);
         Ok(())
     }
+
+    #[test]
+    fn test_fuel_limits() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let mut module_store = ModuleStore::default();
+        module_store.add_with_config(
+            "spin",
+            br#"(module
+                (func (export "spin") (loop br 0))
+                (func (export "nop")))"#,
+            false,
+            ModuleConfig {
+                fuel: Some(1_000),
+                ..Default::default()
+            },
+        )?;
+        let module = module_store.get("spin").unwrap().clone();
+
+        let request = |name: &str, fuel| ExecuteModuleRequest {
+            module_name: "spin".into(),
+            function: WasmFunction {
+                name: name.into(),
+                args: vec![],
+                results: vec![],
+                values: Default::default(),
+            },
+            options: InvocationOptions {
+                fuel,
+                ..Default::default()
+            },
+        };
+
+        let err = runtime
+            .block_on(execute_function(module.clone(), request("spin", None)))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ExecutionError>(),
+            Some(&ExecutionError::OutOfFuel { limit: 1_000 })
+        );
+
+        let err = runtime
+            .block_on(execute_function(module.clone(), request("spin", Some(10))))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ExecutionError>(),
+            Some(&ExecutionError::OutOfFuel { limit: 10 })
+        );
+
+        let output = runtime.block_on(execute_function(module.clone(), request("nop", None)))?;
+        assert!(output.fuel_consumed > 0 && output.fuel_consumed < 10);
+
+        // calls into dependencies run on the same fuel
+        module_store.add(
+            "caller",
+            br#"(module
+                (import "spin" "spin" (func $spin))
+                (import "spin" "nop" (func $nop))
+                (func (export "spin") (call $spin))
+                (func (export "nop") (call $nop)))"#,
+            false,
+        )?;
+        let caller = module_store.get("caller").unwrap().clone();
+
+        let err = runtime
+            .block_on(execute_function(
+                caller.clone(),
+                request("spin", Some(1_000)),
+            ))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ExecutionError>(),
+            Some(&ExecutionError::OutOfFuel { limit: 1_000 })
+        );
+
+        let nested = runtime.block_on(execute_function(caller, request("nop", None)))?;
+        assert!(nested.fuel_consumed > output.fuel_consumed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeouts() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let mut module_store = ModuleStore::default();
+        module_store.add(
+            "spin",
+            br#"(module (func (export "spin") (loop br 0)))"#,
+            false,
+        )?;
+        let module = module_store.get("spin").unwrap().clone();
+
+        let request = |module_name: &str| ExecuteModuleRequest {
+            module_name: module_name.into(),
+            function: WasmFunction {
+                name: "spin".into(),
+                args: vec![],
+                results: vec![],
+                values: Default::default(),
+            },
+            options: InvocationOptions {
+                timeout_ms: Some(50),
+                ..Default::default()
+            },
+        };
+
+        let started = std::time::Instant::now();
+        let err = runtime
+            .block_on(execute_function(module.clone(), request("spin")))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ExecutionError>(),
+            Some(&ExecutionError::Timeout { timeout_ms: 50 })
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        // a call stuck in a dependency is stopped too, and only then gives its slot back
+        module_store.add(
+            "caller",
+            br#"(module
+                (import "spin" "spin" (func $spin))
+                (func (export "spin") (call $spin)))"#,
+            false,
+        )?;
+        let caller = module_store.get("caller").unwrap().clone();
+
+        let err = runtime
+            .block_on(execute_function(caller.clone(), request("caller")))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ExecutionError>(),
+            Some(&ExecutionError::Timeout { timeout_ms: 50 })
+        );
+        while caller.instances.running() > 0 {
+            assert!(started.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        // so is a call whose caller stops waiting for it
+        let abandoned = ExecuteModuleRequest {
+            options: InvocationOptions::default(),
+            ..request("spin")
+        };
+        let started = std::time::Instant::now();
+        let call = execute_function(module.clone(), abandoned);
+        let dropped = runtime.block_on(async {
+            tokio::time::timeout(std::time::Duration::from_millis(50), call).await
+        });
+        assert!(dropped.is_err());
+        while module.instances.running() > 0 {
+            assert!(started.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wasi_output_is_captured() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let mut module_store = ModuleStore::default();
+        module_store.add("greet", WAT_GREET, true)?;
+        module_store.add_with_config(
+            "greet_capped",
+            WAT_GREET,
+            true,
+            ModuleConfig {
+                max_output_bytes: Some(3),
+                ..Default::default()
+            },
+        )?;
+
+        let request = |module_name: &str| ExecuteModuleRequest {
+            module_name: module_name.into(),
+            function: WasmFunction {
+                name: "greet".into(),
+                args: vec![],
+                results: vec![],
+                values: Default::default(),
+            },
+            options: Default::default(),
+        };
+
+        let module = module_store.get("greet").unwrap().clone();
+        let output = runtime.block_on(execute_function(module, request("greet")))?;
+        assert_eq!(output.stdout.as_deref(), Some("hello\n"));
+        assert_eq!(output.stderr.as_deref(), Some("oops\n"));
+        assert!(!output.output_truncated);
+
+        let module = module_store.get("greet_capped").unwrap().clone();
+        let output = runtime.block_on(execute_function(module, request("greet_capped")))?;
+        assert_eq!(output.stdout.as_deref(), Some("hel"));
+        assert!(output.output_truncated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wasi_args_env_and_stdin() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut module_store = ModuleStore::default();
+        module_store.add_with_config(
+            "echo",
+            WAT_ECHO,
+            true,
+            ModuleConfig {
+                args: vec!["--default".into()],
+                env: BTreeMap::from([("LANG".into(), "C".into())]),
+                ..Default::default()
+            },
+        )?;
+        let module = module_store.get("echo").unwrap().clone();
+
+        let request = |options| ExecuteModuleRequest {
+            module_name: "echo".into(),
+            function: WasmFunction {
+                name: "echo".into(),
+                args: vec![],
+                results: vec![],
+                values: Default::default(),
+            },
+            options,
+        };
+
+        // argv[0] is the module name, followed by the module's default args.
+        let output = runtime.block_on(execute_function(
+            module.clone(),
+            request(Default::default()),
+        ))?;
+        assert_eq!(
+            output.results,
+            [
+                WasmValue::Value(Value::I32(2)),
+                WasmValue::Value(Value::I32(1))
+            ]
+        );
+        assert_eq!(output.stdout.as_deref(), Some(""));
+
+        let output = runtime.block_on(execute_function(
+            module.clone(),
+            request(InvocationOptions {
+                argv: Some(vec!["a".into(), "b".into(), "c".into()]),
+                env: BTreeMap::from([("HOME".into(), "/".into())]),
+                stdin: Some(base64::encode("ping")),
+                ..Default::default()
+            }),
+        ))?;
+        assert_eq!(
+            output.results,
+            [
+                WasmValue::Value(Value::I32(4)),
+                WasmValue::Value(Value::I32(2))
+            ]
+        );
+        assert_eq!(output.stdout.as_deref(), Some("ping"));
+
+        let invalid = request(InvocationOptions {
+            stdin: Some("not base64!".into()),
+            ..Default::default()
+        });
+        assert!(runtime.block_on(execute_function(module, invalid)).is_err());
+
+        module_store.add("plain", br#"(module (func (export "echo")))"#, false)?;
+        let plain = module_store.get("plain").unwrap().clone();
+        let request = ExecuteModuleRequest {
+            module_name: "plain".into(),
+            ..request(InvocationOptions {
+                stdin: Some(base64::encode("ping")),
+                ..Default::default()
+            })
+        };
+        let err = runtime
+            .block_on(execute_function(plain, request))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ExecutionError>(),
+            Some(&ExecutionError::WasiOnly { option: "stdin" })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_exit_codes() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut module_store = ModuleStore::default();
+        module_store.add("hello_world", WASM_HELLO_WORLD, true)?;
+        module_store.add("exit", WAT_EXIT, true)?;
+
+        let module = module_store.get("hello_world").unwrap().clone();
+        let request = ExecuteModuleRequest::command("hello_world".into(), Default::default());
+        let output = runtime.block_on(execute_function(module, request))?;
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.stdout.as_deref(), Some("Hello world\n"));
+
+        let module = module_store.get("exit").unwrap().clone();
+        let request = ExecuteModuleRequest::command("exit".into(), Default::default());
+        let output = runtime.block_on(execute_function(module, request))?;
+        assert_eq!(output.exit_code, Some(3));
+        assert!(output.results.is_empty());
+        assert_eq!(output.stderr.as_deref(), Some("failed\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wasi_mounts() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut module_store = ModuleStore::default();
+        module_store.add_with_config(
+            "cat",
+            WAT_CAT,
+            true,
+            ModuleConfig {
+                mounts: vec![Mount::Files {
+                    path: "/config".into(),
+                    files: BTreeMap::from([("greeting.txt".into(), base64::encode("hi"))]),
+                }],
+                ..Default::default()
+            },
+        )?;
+        let module = module_store.get("cat").unwrap().clone();
+
+        let request = |offset: &str, len: &str| ExecuteModuleRequest {
+            module_name: "cat".into(),
+            function: WasmFunction {
+                name: "cat".into(),
+                args: vec![
+                    WasmArg {
+                        value: offset.into(),
+                        arg_type: WasmType::I32,
+                    },
+                    WasmArg {
+                        value: len.into(),
+                        arg_type: WasmType::I32,
+                    },
+                ],
+                results: vec![],
+                values: Default::default(),
+            },
+            options: Default::default(),
+        };
+
+        let output = runtime.block_on(execute_function(module.clone(), request("100", "12")))?;
+        assert_eq!(output.results, [WasmValue::Value(Value::I32(0))]);
+        assert_eq!(output.stdout.as_deref(), Some("hi"));
+
+        let output = runtime.block_on(execute_function(module, request("112", "9")))?;
+        assert_ne!(output.results, [WasmValue::Value(Value::I32(0))]);
+        assert_eq!(output.stdout.as_deref(), Some(""));
+
+        let host_mount = ModuleConfig {
+            mounts: vec![Mount::Host {
+                path: "/etc".into(),
+                host_path: "/etc".into(),
+            }],
+            ..Default::default()
+        };
+        assert!(module_store
+            .add_with_config("cat_etc", WAT_CAT, true, host_mount)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strings_and_bytes() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut module_store = ModuleStore::default();
+        module_store.add("strings", WAT_STRINGS, false)?;
+        module_store.add("sum", WASM_SUM, false)?;
+
+        let request =
+            |module_name: &str, name: &str, args: Vec<WasmArg>, results| ExecuteModuleRequest {
+                module_name: module_name.into(),
+                function: WasmFunction {
+                    name: name.into(),
+                    args,
+                    results,
+                    values: Default::default(),
+                },
+                options: Default::default(),
+            };
+        let arg = |value: &str, arg_type| WasmArg {
+            value: value.into(),
+            arg_type,
+        };
+        let module = module_store.get("strings").unwrap().clone();
+
+        let upper = request(
+            "strings",
+            "upper",
+            vec![arg("hello, wasm", WasmType::String)],
+            vec![WasmType::String],
+        );
+        let output = runtime.block_on(execute_function(module.clone(), upper))?;
+        assert_eq!(output.results, [WasmValue::String("HELLO, WASM".into())]);
+
+        let len = request(
+            "strings",
+            "len",
+            vec![arg(&base64::encode([1, 2, 3]), WasmType::Bytes)],
+            vec![],
+        );
+        let output = runtime.block_on(execute_function(module.clone(), len))?;
+        assert_eq!(output.results, [WasmValue::Value(Value::I32(3))]);
+
+        let greeting = request("strings", "greeting", vec![], vec![WasmType::Bytes]);
+        let output = runtime.block_on(execute_function(module, greeting))?;
+        assert_eq!(output.results, [WasmValue::Bytes(b"hello".to_vec())]);
+
+        // Strings need an allocator the module doesn't export.
+        let module = module_store.get("sum").unwrap().clone();
+        let sum = request("sum", "sum", vec![arg("12", WasmType::String)], vec![]);
+        let err = runtime
+            .block_on(execute_function(module.clone(), sum))
+            .unwrap_err();
+        assert!(err.is::<GuestMemoryError>());
+
+        // Each string takes two params, which `sum` doesn't have.
+        let sum = request(
+            "sum",
+            "sum",
+            vec![arg("1", WasmType::String), arg("2", WasmType::String)],
+            vec![],
+        );
+        let err = runtime.block_on(execute_function(module, sum)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SignatureError::ArgumentMismatch { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wit_interface() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut module_store = ModuleStore::default();
+        let config = ModuleConfig {
+            wit: Some(WIT_SHAPES.to_owned()),
+            ..Default::default()
+        };
+        module_store.add_with_config("shapes", WAT_SHAPES, false, config)?;
+        let module = module_store.get("shapes").unwrap().clone();
+
+        let call = |name: &str, values: Vec<serde_json::Value>| {
+            let values = ArgValues::Positional(values);
+            let request = ExecuteModuleRequest {
+                module_name: "shapes".into(),
+                function: WasmFunction {
+                    name: name.into(),
+                    args: vec![],
+                    results: vec![],
+                    values,
+                },
+                options: Default::default(),
+            };
+            runtime
+                .block_on(execute_function(module.clone(), request))
+                .map(|output| output.value)
+        };
+
+        assert_eq!(call("echo", vec![json!("héllo")])?, Some(json!("héllo")));
+        assert_eq!(
+            call("scale", vec![json!({ "x": 2, "y": -3 }), json!(4)])?,
+            Some(json!({ "x": 8, "y": -12 }))
+        );
+        assert_eq!(call("sum", vec![json!([1, 2, 3, -10])])?, Some(json!(-4)));
+        let request = ExecuteModuleRequest {
+            module_name: "shapes".into(),
+            function: WasmFunction {
+                name: "scale".into(),
+                args: vec![],
+                results: vec![],
+                values: serde_json::from_value(json!({ "by": 2, "p": { "x": 1, "y": 2 } }))?,
+            },
+            options: Default::default(),
+        };
+        let output = runtime.block_on(execute_function(module.clone(), request))?;
+        assert_eq!(output.value, Some(json!({ "x": 2, "y": 4 })));
+        assert_eq!(
+            call("kind", vec![json!({ "rect": { "x": 1, "y": 2 } })])?,
+            Some(json!(102))
+        );
+        assert_eq!(call("kind", vec![json!("empty")])?, Some(json!(200)));
+        assert_eq!(call("lookup", vec![json!("")])?, Some(json!("none")));
+        assert_eq!(
+            call("lookup", vec![json!("key")])?,
+            Some(json!({ "some": "key" }))
+        );
+        assert_eq!(call("ratio", vec![json!(1), json!(4)])?, Some(json!(0.25)));
+        assert_eq!(call("ratio", vec![json!(1), json!(0)])?, Some(json!("inf")));
+        assert_eq!(
+            call("ratio", vec![json!("-inf"), json!(1)])?,
+            Some(json!("-inf"))
+        );
+        assert_eq!(
+            call("negate", vec![json!("-9007199254740993")])?,
+            Some(json!("9007199254740993"))
+        );
+        assert_eq!(call("negate", vec![json!(42)])?, Some(json!(-42)));
+
+        assert!(call("scale", vec![json!({ "x": 2 }), json!(4)]).is_err());
+        assert!(call("kind", vec![json!("square")]).is_err());
+        assert!(call("sum", vec![json!([1]), json!(2)]).is_err());
+        // A list length whose size in bytes doesn't fit the address space.
+        let error = call("points", vec![]).unwrap_err().to_string();
+        assert!(error.contains("outside of the module memory"), "{}", error);
+
+        let mismatched = ModuleConfig {
+            wit: Some("echo: function(s: string) -> u64".to_owned()),
+            ..Default::default()
+        };
+        let err = module_store
+            .add_with_config("mismatched", WAT_SHAPES, false, mismatched)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(WitError::SignatureMismatch { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vector_and_reference_values() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut module_store = ModuleStore::default();
+        module_store.add("refs", WAT_REFS, false)?;
+        let module = module_store.get("refs").unwrap().clone();
+
+        let call = |name: &str, args: Vec<(&str, WasmType)>| {
+            let request = ExecuteModuleRequest {
+                module_name: "refs".into(),
+                function: WasmFunction {
+                    name: name.into(),
+                    args: args
+                        .into_iter()
+                        .map(|(value, arg_type)| WasmArg {
+                            value: value.into(),
+                            arg_type,
+                        })
+                        .collect(),
+                    results: vec![],
+                    values: Default::default(),
+                },
+                options: Default::default(),
+            };
+            let output = runtime.block_on(execute_function(module.clone(), request))?;
+            Ok::<_, anyhow::Error>(
+                output
+                    .results
+                    .into_iter()
+                    .map(|value| WasmResult::from(value).result)
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(
+            call(
+                "add",
+                vec![
+                    ("i32x4 1 2 3 4", WasmType::V128),
+                    ("0x00000001000000010000000100000001", WasmType::V128),
+                ]
+            )?,
+            ["0x00000005000000040000000300000002"]
+        );
+        assert_eq!(call("keep", vec![("7", WasmType::ExternRef)])?, ["7"]);
+        assert_eq!(
+            call("keep", vec![("null", WasmType::ExternRef)])?,
+            [json!(null)]
+        );
+        assert_eq!(
+            call("pick", vec![("double", WasmType::FuncRef)])?,
+            ["double"]
+        );
+        assert_eq!(
+            call("pick", vec![("null", WasmType::FuncRef)])?,
+            [json!(null)]
+        );
+
+        assert!(call("pick", vec![("missing", WasmType::FuncRef)]).is_err());
+        assert!(call(
+            "add",
+            vec![("i32x4 1", WasmType::V128), ("0", WasmType::V128)]
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inferred_args() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut module_store = ModuleStore::default();
+        module_store.add("sum", WASM_SUM, false)?;
+        let module = module_store.get("sum").unwrap().clone();
+
+        let call = |values: serde_json::Value| {
+            let request = ExecuteModuleRequest {
+                module_name: "sum".into(),
+                function: WasmFunction {
+                    name: "sum".into(),
+                    args: vec![],
+                    results: vec![],
+                    values: serde_json::from_value(values)?,
+                },
+                options: Default::default(),
+            };
+            runtime.block_on(execute_function(module.clone(), request))
+        };
+
+        let output = call(json!([2, "3"]))?;
+        assert_eq!(output.results, [WasmValue::Value(Value::I32(5))]);
+
+        let signature_error = |values| {
+            call(values)
+                .unwrap_err()
+                .downcast::<SignatureError>()
+                .unwrap()
+        };
+        assert!(matches!(
+            signature_error(json!([1])),
+            SignatureError::ArityMismatch {
+                expected: 2,
+                found: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            signature_error(json!([1, 1u64 << 32])),
+            SignatureError::InvalidArgument { param, .. } if param == "1"
+        ));
+        assert!(matches!(
+            signature_error(json!([1, 1.5])),
+            SignatureError::InvalidArgument { .. }
+        ));
+        assert!(matches!(
+            signature_error(json!({ "a": 1, "b": 2 })),
+            SignatureError::UnnamedParams(_)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_arity() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut module_store = ModuleStore::default();
+        module_store.add("results", WAT_RESULTS, false)?;
+        let module = module_store.get("results").unwrap().clone();
+
+        let call = |name: &str| {
+            let request = ExecuteModuleRequest {
+                module_name: "results".into(),
+                function: WasmFunction {
+                    name: name.into(),
+                    args: vec![],
+                    results: vec![],
+                    values: Default::default(),
+                },
+                options: Default::default(),
+            };
+            let output = runtime.block_on(execute_function(module.clone(), request))?;
+            Ok::<_, anyhow::Error>(serde_json::to_value(ExecuteModuleResponse::from(output))?)
+        };
+
+        assert_eq!(call("none")?["results"], json!([]));
+        assert_eq!(
+            call("one")?["results"],
+            json!([{ "result": 0.5, "resultType": "F64" }])
+        );
+        assert_eq!(
+            call("many")?["results"],
+            json!([
+                { "result": -1, "resultType": "I32" },
+                { "result": "9007199254740993", "resultType": "I64" },
+                { "result": 1.5, "resultType": "F32" },
+            ])
+        );
+
+        Ok(())
+    }
 }
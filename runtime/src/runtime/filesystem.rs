@@ -0,0 +1,486 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use wasmer_vfs::{
+    host_fs, mem_fs, DirEntry, FileOpener, FileSystem, FsError, Metadata, OpenOptions,
+    OpenOptionsConfig, ReadDir, VirtualFile,
+};
+use wasmer_wasi::WasiStateBuilder;
+
+/// A directory preopened for a WASI module, at `path` in the guest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Mount {
+    /// Read-only view of a host directory inside one of the server's allowed directories.
+    #[serde(rename_all = "camelCase")]
+    Host { path: String, host_path: PathBuf },
+    /// Empty in-memory directory, discarded after every invocation.
+    Tmpfs { path: String },
+    /// Read-only directory of files uploaded with the module, base64 encoded and keyed by
+    /// their path inside the directory.
+    Files {
+        path: String,
+        files: BTreeMap<String, String>,
+    },
+}
+
+impl Mount {
+    pub fn path(&self) -> &str {
+        match self {
+            Mount::Host { path, .. } | Mount::Tmpfs { path } | Mount::Files { path, .. } => path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountError {
+    /// Guest paths must be absolute and free of `.` and `..`, file paths relative and free of them.
+    InvalidPath(String),
+    Overlapping {
+        path: String,
+        other: String,
+    },
+    /// The host directory doesn't exist or is outside of the allowed directories.
+    HostDirNotAllowed(PathBuf),
+    InvalidFile {
+        path: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for MountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MountError::InvalidPath(path) => write!(f, "invalid mount path: {}", path),
+            MountError::Overlapping { path, other } => {
+                write!(f, "mount {} overlaps mount {}", path, other)
+            }
+            MountError::HostDirNotAllowed(path) => {
+                write!(f, "host directory {} can't be mounted", path.display())
+            }
+            MountError::InvalidFile { path, reason } => {
+                write!(f, "invalid uploaded file {}: {}", path, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MountError {}
+
+/// The mounts of a module version, checked and decoded at registration.
+#[derive(Debug, Default)]
+pub struct Mounts {
+    mounts: Vec<PreparedMount>,
+}
+
+#[derive(Debug)]
+struct PreparedMount {
+    path: PathBuf,
+    source: Source,
+}
+
+#[derive(Debug)]
+enum Source {
+    /// Canonical path of the host directory.
+    Host(PathBuf),
+    Tmpfs,
+    Files(Vec<(PathBuf, Vec<u8>)>),
+}
+
+impl Mounts {
+    /// Validates `mounts`, only allowing host directories inside one of `host_dirs`.
+    pub fn new(mounts: &[Mount], host_dirs: &[PathBuf]) -> Result<Self, MountError> {
+        let host_dirs = host_dirs
+            .iter()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .collect::<Vec<_>>();
+
+        let mut prepared: Vec<PreparedMount> = Vec::with_capacity(mounts.len());
+        for mount in mounts {
+            let path = guest_path(mount.path())?;
+            if let Some(other) = prepared
+                .iter()
+                .find(|other| other.path.starts_with(&path) || path.starts_with(&other.path))
+            {
+                return Err(MountError::Overlapping {
+                    path: mount.path().to_owned(),
+                    other: other.path.display().to_string(),
+                });
+            }
+
+            let source = match mount {
+                Mount::Host { host_path, .. } => {
+                    let not_allowed = || MountError::HostDirNotAllowed(host_path.clone());
+                    let dir = host_path.canonicalize().map_err(|_| not_allowed())?;
+                    if !dir.is_dir() || !host_dirs.iter().any(|allowed| dir.starts_with(allowed)) {
+                        return Err(not_allowed());
+                    }
+                    Source::Host(dir)
+                }
+                Mount::Tmpfs { .. } => Source::Tmpfs,
+                Mount::Files { files, .. } => {
+                    let files = files
+                        .iter()
+                        .map(|(name, data)| decode_file(name, data))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    // Catches files that are also used as directories before any call does.
+                    populate(&files).map_err(|err| MountError::InvalidFile {
+                        path: mount.path().to_owned(),
+                        reason: err.to_string(),
+                    })?;
+                    Source::Files(files)
+                }
+            };
+
+            prepared.push(PreparedMount { path, source });
+        }
+
+        Ok(Self { mounts: prepared })
+    }
+
+    /// Preopens the mounts for one invocation.
+    pub fn preopen(&self, builder: &mut WasiStateBuilder) -> anyhow::Result<()> {
+        let fs = self.filesystem()?;
+        for mount in fs.mounts.iter() {
+            let alias = mount.path.to_string_lossy();
+            builder.preopen(|dir| {
+                dir.directory(&mount.path)
+                    .alias(&alias)
+                    .read(true)
+                    .write(!mount.read_only)
+                    .create(!mount.read_only)
+            })?;
+        }
+
+        // Set even without mounts, so the module never sees the host filesystem.
+        builder.set_fs(Box::new(fs));
+
+        Ok(())
+    }
+
+    /// Tmpfs and uploaded files get a fresh in-memory copy, so nothing written survives the
+    /// invocation.
+    fn filesystem(&self) -> anyhow::Result<SandboxFs> {
+        let mut mounts = Vec::with_capacity(self.mounts.len());
+        for mount in &self.mounts {
+            let (backing, read_only) = match &mount.source {
+                Source::Host(dir) => (Backing::Host(dir.clone()), true),
+                Source::Tmpfs => (Backing::Memory(mem_fs::FileSystem::default()), false),
+                Source::Files(files) => (Backing::Memory(populate(files)?), true),
+            };
+            mounts.push(SandboxMount {
+                path: mount.path.clone(),
+                backing,
+                read_only,
+            });
+        }
+
+        Ok(SandboxFs {
+            mounts: Arc::new(mounts),
+        })
+    }
+}
+
+fn guest_path(path: &str) -> Result<PathBuf, MountError> {
+    let guest = Path::new(path);
+    let mut components = guest.components();
+    let valid = components.next() == Some(Component::RootDir)
+        && components.clone().next().is_some()
+        && components.all(|component| matches!(component, Component::Normal(_)))
+        && !path.contains('\0');
+
+    match valid {
+        true => Ok(guest.to_path_buf()),
+        false => Err(MountError::InvalidPath(path.to_owned())),
+    }
+}
+
+fn decode_file(name: &str, data: &str) -> Result<(PathBuf, Vec<u8>), MountError> {
+    let path = Path::new(name);
+    let valid = path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !valid {
+        return Err(MountError::InvalidPath(name.to_owned()));
+    }
+
+    let data = base64::decode(data).map_err(|err| MountError::InvalidFile {
+        path: name.to_owned(),
+        reason: err.to_string(),
+    })?;
+
+    Ok((path.to_path_buf(), data))
+}
+
+fn populate(files: &[(PathBuf, Vec<u8>)]) -> anyhow::Result<mem_fs::FileSystem> {
+    let fs = mem_fs::FileSystem::default();
+    for (path, data) in files {
+        let path = Path::new("/").join(path);
+        let mut parents = path.ancestors().skip(1).collect::<Vec<_>>();
+        parents.pop();
+        for parent in parents.into_iter().rev() {
+            match fs.create_dir(parent) {
+                Ok(()) | Err(FsError::AlreadyExists) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        fs.new_open_options()
+            .write(true)
+            .create_new(true)
+            .open(&path)?
+            .write_all(data)?;
+    }
+
+    Ok(fs)
+}
+
+/// Routes guest paths to the mount they fall in, rejecting paths that would leave it and
+/// writes to read-only mounts.
+#[derive(Debug, Clone)]
+struct SandboxFs {
+    mounts: Arc<Vec<SandboxMount>>,
+}
+
+#[derive(Debug)]
+struct SandboxMount {
+    path: PathBuf,
+    backing: Backing,
+    read_only: bool,
+}
+
+#[derive(Debug)]
+enum Backing {
+    Host(PathBuf),
+    Memory(mem_fs::FileSystem),
+}
+
+impl SandboxMount {
+    fn fs(&self) -> &dyn FileSystem {
+        match &self.backing {
+            Backing::Host(_) => &host_fs::FileSystem,
+            Backing::Memory(fs) => fs,
+        }
+    }
+
+    fn writable(&self) -> Result<&Self, FsError> {
+        match self.read_only {
+            true => Err(FsError::PermissionDenied),
+            false => Ok(self),
+        }
+    }
+}
+
+impl SandboxFs {
+    /// Finds the mount of a guest path and the path it maps to in the mount's backing.
+    fn resolve(&self, path: &Path) -> Result<(&SandboxMount, PathBuf), FsError> {
+        let mount = self
+            .mounts
+            .iter()
+            .find(|mount| path.starts_with(&mount.path))
+            .ok_or(FsError::EntityNotFound)?;
+
+        let mut relative = PathBuf::new();
+        for component in path.strip_prefix(&mount.path).unwrap().components() {
+            match component {
+                Component::Normal(name) => relative.push(name),
+                Component::CurDir => {}
+                _ => return Err(FsError::PermissionDenied),
+            }
+        }
+
+        let target = match &mount.backing {
+            Backing::Host(root) => {
+                // Symlinks inside the directory may still point out of it.
+                let target = root
+                    .join(relative)
+                    .canonicalize()
+                    .map_err(|_| FsError::EntityNotFound)?;
+                if !target.starts_with(root) {
+                    return Err(FsError::PermissionDenied);
+                }
+                target
+            }
+            Backing::Memory(_) => Path::new("/").join(relative),
+        };
+
+        Ok((mount, target))
+    }
+}
+
+impl FileSystem for SandboxFs {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        let (mount, target) = self.resolve(path)?;
+        let entries = mount
+            .fs()
+            .read_dir(&target)?
+            .map(|entry| {
+                entry.map(|entry| DirEntry {
+                    path: path.join(entry.file_name()),
+                    metadata: entry.metadata,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ReadDir::new(entries))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), FsError> {
+        let (mount, target) = self.resolve(path)?;
+        mount.writable()?.fs().create_dir(&target)
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<(), FsError> {
+        let (mount, target) = self.resolve(path)?;
+        if path == mount.path {
+            return Err(FsError::PermissionDenied);
+        }
+        mount.writable()?.fs().remove_dir(&target)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), FsError> {
+        let (mount, from) = self.resolve(from)?;
+        let (to_mount, to) = self.resolve(to)?;
+        if !std::ptr::eq(mount, to_mount) {
+            return Err(FsError::PermissionDenied);
+        }
+        mount.writable()?.fs().rename(&from, &to)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        let (mount, target) = self.resolve(path)?;
+        mount.fs().metadata(&target)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), FsError> {
+        let (mount, target) = self.resolve(path)?;
+        mount.writable()?.fs().remove_file(&target)
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(Box::new(self.clone()))
+    }
+}
+
+impl FileOpener for SandboxFs {
+    fn open(
+        &mut self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile>, FsError> {
+        let (mount, target) = self.resolve(path)?;
+        let writes = conf.write() || conf.append() || conf.truncate();
+        let creates = conf.create() || conf.create_new();
+        if writes || creates {
+            mount.writable()?;
+        }
+
+        mount
+            .fs()
+            .new_open_options()
+            .read(conf.read())
+            .write(conf.write())
+            .append(conf.append())
+            .truncate(conf.truncate())
+            .create(conf.create())
+            .create_new(conf.create_new())
+            .open(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Read, path::Path};
+
+    use wasmer_vfs::FileSystem;
+
+    use super::{Mount, MountError, Mounts};
+
+    #[test]
+    fn test_mounts_stay_inside_their_directories() -> anyhow::Result<()> {
+        let allowed = tempfile::tempdir()?;
+        let shared = allowed.path().join("shared");
+        std::fs::create_dir(&shared)?;
+        std::fs::write(shared.join("hello.txt"), "hello")?;
+        std::fs::write(allowed.path().join("secret.txt"), "secret")?;
+
+        let mounts = Mounts::new(
+            &[
+                Mount::Host {
+                    path: "/shared".into(),
+                    host_path: shared.clone(),
+                },
+                Mount::Tmpfs {
+                    path: "/tmp".into(),
+                },
+                Mount::Files {
+                    path: "/config".into(),
+                    files: BTreeMap::from([("app/settings.json".into(), base64::encode("{}"))]),
+                },
+            ],
+            &[allowed.path().to_path_buf()],
+        )?;
+        let fs = mounts.filesystem()?;
+
+        let mut contents = String::new();
+        fs.new_open_options()
+            .read(true)
+            .open("/shared/hello.txt")?
+            .read_to_string(&mut contents)?;
+        assert_eq!(contents, "hello");
+        assert!(fs
+            .metadata(Path::new("/config/app/settings.json"))?
+            .is_file());
+
+        assert!(fs
+            .new_open_options()
+            .read(true)
+            .open("/shared/../secret.txt")
+            .is_err());
+        assert!(fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/shared/new.txt")
+            .is_err());
+        assert!(fs
+            .remove_file(Path::new("/config/app/settings.json"))
+            .is_err());
+
+        fs.create_dir(Path::new("/tmp/scratch"))?;
+        assert!(fs.metadata(Path::new("/tmp/scratch"))?.is_dir());
+        // Every invocation starts from an empty tmpfs.
+        assert!(mounts
+            .filesystem()?
+            .metadata(Path::new("/tmp/scratch"))
+            .is_err());
+
+        let outside = Mounts::new(
+            &[Mount::Host {
+                path: "/etc".into(),
+                host_path: "/etc".into(),
+            }],
+            &[allowed.path().to_path_buf()],
+        );
+        assert_eq!(
+            outside.err(),
+            Some(MountError::HostDirNotAllowed("/etc".into()))
+        );
+        let escaping = Mounts::new(
+            &[Mount::Tmpfs {
+                path: "/tmp/../etc".into(),
+            }],
+            &[],
+        );
+        assert!(matches!(escaping, Err(MountError::InvalidPath(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+
+use wasmer::{Function, Instance, Memory, Type, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuestMemoryError {
+    /// Passing strings or bytes needs an exported `memory`.
+    NoMemory,
+    /// Passing strings or bytes needs an exported `canonical_abi_realloc`, `malloc` or `alloc`.
+    NoAllocator,
+    OutOfBounds {
+        ptr: u32,
+        len: u32,
+    },
+}
+
+impl fmt::Display for GuestMemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuestMemoryError::NoMemory => write!(f, "module does not export its memory"),
+            GuestMemoryError::NoAllocator => write!(
+                f,
+                "module does not export canonical_abi_realloc, malloc or alloc"
+            ),
+            GuestMemoryError::OutOfBounds { ptr, len } => write!(
+                f,
+                "{} bytes at {:#x} are outside of the module memory",
+                len, ptr
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GuestMemoryError {}
+
+/// Copies data in and out of an instance's exported memory, using the allocator the module
+/// exports. Buffers are `(ptr, len)` pairs of `i32`s.
+pub struct GuestMemory {
+    memory: Memory,
+    alloc: Alloc,
+    free: Option<Dealloc>,
+}
+
+enum Alloc {
+    /// `canonical_abi_realloc(ptr, old_size, align, new_size)`, as generated by wit-bindgen.
+    Realloc(Function),
+    /// `malloc(size)` or `alloc(size)`.
+    Malloc(Function),
+}
+
+enum Dealloc {
+    /// `canonical_abi_free(ptr, size, align)`.
+    Canonical(Function),
+    /// `free(ptr)`.
+    Free(Function),
+    /// `dealloc(ptr, size)`.
+    Sized(Function),
+}
+
+impl GuestMemory {
+    pub fn new(instance: &Instance) -> Result<Self, GuestMemoryError> {
+        let memory = instance
+            .exports
+            .get_memory("memory")
+            .map_err(|_| GuestMemoryError::NoMemory)?
+            .clone();
+
+        let function = |name: &str, params: &[Type], results: &[Type]| {
+            instance
+                .exports
+                .get_function(name)
+                .ok()
+                .filter(|function| {
+                    let ty = function.ty();
+                    ty.params() == params && ty.results() == results
+                })
+                .cloned()
+        };
+        use Type::I32;
+
+        let alloc = function("canonical_abi_realloc", &[I32; 4], &[I32])
+            .map(Alloc::Realloc)
+            .or_else(|| function("malloc", &[I32], &[I32]).map(Alloc::Malloc))
+            .or_else(|| function("alloc", &[I32], &[I32]).map(Alloc::Malloc))
+            .ok_or(GuestMemoryError::NoAllocator)?;
+        let free = function("canonical_abi_free", &[I32; 3], &[])
+            .map(Dealloc::Canonical)
+            .or_else(|| function("free", &[I32], &[]).map(Dealloc::Free))
+            .or_else(|| function("dealloc", &[I32; 2], &[]).map(Dealloc::Sized));
+
+        Ok(Self {
+            memory,
+            alloc,
+            free,
+        })
+    }
+
+    /// Allocates `size` bytes in the guest. `align` is only honoured by `canonical_abi_realloc`,
+    /// `malloc` style allocators align for any type already.
+    pub fn alloc(&self, size: usize, align: usize) -> anyhow::Result<i32> {
+        let size = Value::I32(size as i32);
+        let ptr = match &self.alloc {
+            Alloc::Realloc(realloc) => {
+                realloc.call(&[Value::I32(0), Value::I32(0), Value::I32(align as i32), size])?
+            }
+            Alloc::Malloc(malloc) => malloc.call(&[size])?,
+        };
+
+        Ok(ptr[0].unwrap_i32())
+    }
+
+    /// Allocates a buffer in the guest and copies `data` into it.
+    pub fn write(&self, data: &[u8]) -> anyhow::Result<(i32, i32)> {
+        let ptr = self.alloc(data.len(), 1)?;
+        self.write_at(ptr, data)?;
+
+        Ok((ptr, data.len() as i32))
+    }
+
+    pub fn write_at(&self, ptr: i32, data: &[u8]) -> Result<(), GuestMemoryError> {
+        let view = self.memory.uint8view();
+        let start = ptr as u32 as usize;
+        let cells = view
+            .get(start..start + data.len())
+            .ok_or(GuestMemoryError::OutOfBounds {
+                ptr: ptr as u32,
+                len: data.len() as u32,
+            })?;
+        for (cell, byte) in cells.iter().zip(data) {
+            cell.set(*byte);
+        }
+
+        Ok(())
+    }
+
+    pub fn read(&self, ptr: i32, len: i32) -> Result<Vec<u8>, GuestMemoryError> {
+        let start = ptr as u32 as usize;
+        self.memory
+            .uint8view()
+            .get(start..start + len as u32 as usize)
+            .map(|cells| cells.iter().map(|cell| cell.get()).collect())
+            .ok_or(GuestMemoryError::OutOfBounds {
+                ptr: ptr as u32,
+                len: len as u32,
+            })
+    }
+
+    /// Fails unless `len` bytes at `ptr` are inside the memory.
+    pub fn check_bounds(&self, ptr: i32, len: u32) -> Result<(), GuestMemoryError> {
+        let end = ptr as u32 as u64 + len as u64;
+        match end <= self.memory.data_size() {
+            true => Ok(()),
+            false => Err(GuestMemoryError::OutOfBounds {
+                ptr: ptr as u32,
+                len,
+            }),
+        }
+    }
+
+    /// Reads the `(ptr, len)` pair stored at `area`, the way multiple results are returned
+    /// without multi-value.
+    pub fn read_pair(&self, area: i32) -> Result<(i32, i32), GuestMemoryError> {
+        let bytes = self.read(area, 8)?;
+        let word = |at: usize| i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        Ok((word(0), word(4)))
+    }
+
+    /// Hands a buffer back to the guest allocator. A no-op for modules that don't export a
+    /// deallocator.
+    pub fn free(&self, ptr: i32, len: i32) -> anyhow::Result<()> {
+        self.free_aligned(ptr, len, 1)
+    }
+
+    pub fn free_aligned(&self, ptr: i32, len: i32, align: usize) -> anyhow::Result<()> {
+        match &self.free {
+            Some(Dealloc::Canonical(free)) => {
+                free.call(&[Value::I32(ptr), Value::I32(len), Value::I32(align as i32)])?
+            }
+            Some(Dealloc::Free(free)) => free.call(&[Value::I32(ptr)])?,
+            Some(Dealloc::Sized(dealloc)) => dealloc.call(&[Value::I32(ptr), Value::I32(len)])?,
+            None => return Ok(()),
+        };
+
+        Ok(())
+    }
+}
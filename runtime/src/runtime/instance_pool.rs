@@ -0,0 +1,386 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use wasmer::{
+    ExportIndex, Extern, ExternType, Function, Global, Instance, Memory, Module, Mutability,
+    NamedResolver, Pages, Table, Type, Val,
+};
+
+use crate::module_store::{LinkedInstance, ModulePackage};
+
+/// Instances kept around by a reusing pool unless the module config says otherwise.
+pub const DEFAULT_POOL_SIZE: usize = 4;
+
+/// Whether invocations get an instance of their own or share reset instances.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InstancePolicy {
+    /// Every invocation instantiates the module, so no state survives between calls.
+    #[default]
+    Fresh,
+    /// Instances are restored to their freshly instantiated state after a call and reused.
+    Reuse,
+}
+
+impl InstancePolicy {
+    pub fn is_fresh(&self) -> bool {
+        *self == InstancePolicy::Fresh
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceStats {
+    pub policy: InstancePolicy,
+    pub pool_size: usize,
+    /// Instances currently waiting in the pool.
+    pub idle: usize,
+    pub cold_starts: u64,
+    pub warm_starts: u64,
+}
+
+/// Pre-instantiated instances of one module version.
+#[derive(Default)]
+pub struct InstancePool {
+    policy: InstancePolicy,
+    size: usize,
+    idle: Mutex<Vec<PooledInstance>>,
+    cold_starts: AtomicU64,
+    warm_starts: AtomicU64,
+}
+
+impl fmt::Debug for InstancePool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstancePool")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// An instance checked out of the pool for a single invocation.
+pub struct InstanceLease {
+    pub instance: Instance,
+    /// Instances of the module's dependencies, created for this instance alone.
+    pub dependencies: Vec<Instance>,
+    /// Whether the instance came out of the pool rather than being created for this call.
+    pub warm: bool,
+    snapshot: Option<Snapshot>,
+}
+
+struct PooledInstance {
+    linked: LinkedInstance,
+    snapshot: Snapshot,
+}
+
+/// Everything a call can change in an instance and its dependencies, as it was right after
+/// instantiation.
+struct Snapshot {
+    memories: Vec<(Memory, Pages, Vec<u8>)>,
+    globals: Vec<(Global, Val)>,
+    tables: Vec<(Table, Vec<Option<Function>>)>,
+}
+
+// SAFETY: `Val` is only `!Send` because of extern refs. Snapshots never hold one:
+// `reuse_blocker` rejects modules with externref tables or reference-typed mutable globals,
+// and the module store runs it on every dependency of a reusing module too.
+unsafe impl Send for Snapshot {}
+unsafe impl Sync for Snapshot {}
+
+impl InstancePool {
+    pub fn new(policy: InstancePolicy, size: usize) -> Self {
+        Self {
+            policy,
+            size,
+            ..Self::default()
+        }
+    }
+
+    /// Instantiates the module up front so the first calls start warm.
+    pub fn fill(&self, package: &ModulePackage) -> anyhow::Result<()> {
+        if self.policy.is_fresh() {
+            return Ok(());
+        }
+
+        let mut instances = Vec::with_capacity(self.size);
+        for _ in 0..self.size {
+            let linked = package.instantiate(&package.imports)?;
+            let snapshot = Snapshot::take(&linked);
+            instances.push(PooledInstance { linked, snapshot });
+        }
+        *self.idle.lock() = instances;
+
+        Ok(())
+    }
+
+    /// Takes an idle instance, or instantiates the module against `imports` when none is left.
+    pub fn acquire(
+        &self,
+        package: &ModulePackage,
+        imports: impl NamedResolver + Send + Sync,
+    ) -> anyhow::Result<InstanceLease> {
+        if let Some(pooled) = self.idle.lock().pop() {
+            self.warm_starts.fetch_add(1, Ordering::Relaxed);
+            return Ok(InstanceLease::new(
+                pooled.linked,
+                true,
+                Some(pooled.snapshot),
+            ));
+        }
+
+        self.cold_starts.fetch_add(1, Ordering::Relaxed);
+        let linked = package.instantiate(imports)?;
+        let snapshot = match self.policy {
+            InstancePolicy::Fresh => None,
+            InstancePolicy::Reuse => Some(Snapshot::take(&linked)),
+        };
+
+        Ok(InstanceLease::new(linked, false, snapshot))
+    }
+
+    /// Resets the instance and puts it back in the pool. Instances whose call failed are
+    /// dropped instead, as are those that grew in a way that can't be undone.
+    pub fn release(&self, lease: InstanceLease, succeeded: bool) {
+        let snapshot = match lease.snapshot {
+            Some(snapshot) if succeeded => snapshot,
+            _ => return,
+        };
+
+        let mut idle = self.idle.lock();
+        if idle.len() < self.size && snapshot.restore() {
+            idle.push(PooledInstance {
+                linked: LinkedInstance {
+                    instance: lease.instance,
+                    dependencies: lease.dependencies,
+                },
+                snapshot,
+            });
+        }
+    }
+
+    pub fn stats(&self) -> InstanceStats {
+        InstanceStats {
+            policy: self.policy,
+            pool_size: self.size,
+            idle: self.idle.lock().len(),
+            cold_starts: self.cold_starts.load(Ordering::Relaxed),
+            warm_starts: self.warm_starts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl InstanceLease {
+    fn new(linked: LinkedInstance, warm: bool, snapshot: Option<Snapshot>) -> Self {
+        Self {
+            instance: linked.instance,
+            dependencies: linked.dependencies,
+            warm,
+            snapshot,
+        }
+    }
+
+    /// The instance and its dependencies, everything a call to it can run in.
+    pub fn instances(&self) -> impl Iterator<Item = &Instance> {
+        self.dependencies.iter().chain([&self.instance])
+    }
+}
+
+/// Explains why instances of `module` can't be safely reset, if they can't. Only exported
+/// state can be restored, so every memory, table and mutable global the module defines must
+/// be exported.
+pub fn reuse_blocker(module: &Module) -> Option<String> {
+    let info = module.info();
+
+    let exported = info.exports.values().cloned().collect::<HashSet<_>>();
+    // Imported entities come first in each index space and belong to another instance.
+    let imported =
+        |kind: fn(&ExternType) -> bool| module.imports().filter(|import| kind(import.ty())).count();
+
+    let memories = imported(|ty| matches!(ty, ExternType::Memory(_)));
+    if info
+        .memories
+        .keys()
+        .skip(memories)
+        .any(|index| !exported.contains(&ExportIndex::Memory(index)))
+    {
+        return Some("it defines a memory that is not exported".to_owned());
+    }
+
+    let tables = imported(|ty| matches!(ty, ExternType::Table(_)));
+    for (index, table) in info.tables.iter().skip(tables) {
+        if !exported.contains(&ExportIndex::Table(index)) {
+            return Some("it defines a table that is not exported".to_owned());
+        }
+        if table.ty == Type::ExternRef {
+            return Some("it defines an externref table".to_owned());
+        }
+    }
+
+    let globals = imported(|ty| matches!(ty, ExternType::Global(_)));
+    for (index, global) in info.globals.iter().skip(globals) {
+        if global.mutability != Mutability::Var {
+            continue;
+        }
+        if !exported.contains(&ExportIndex::Global(index)) {
+            return Some("it defines a mutable global that is not exported".to_owned());
+        }
+        if matches!(global.ty, Type::ExternRef | Type::FuncRef) {
+            return Some("it defines a mutable reference global".to_owned());
+        }
+    }
+
+    None
+}
+
+impl Snapshot {
+    fn take(linked: &LinkedInstance) -> Self {
+        let mut snapshot = Snapshot {
+            memories: Vec::new(),
+            globals: Vec::new(),
+            tables: Vec::new(),
+        };
+
+        let exports = linked
+            .dependencies
+            .iter()
+            .chain([&linked.instance])
+            .flat_map(|instance| instance.exports.iter());
+        for (_, export) in exports {
+            match export {
+                Extern::Memory(memory) => {
+                    // SAFETY: the instance is not running while it is being snapshotted.
+                    let data = unsafe { memory.data_unchecked() }.to_vec();
+                    snapshot
+                        .memories
+                        .push((memory.clone(), memory.size(), data));
+                }
+                Extern::Global(global) if global.ty().mutability == Mutability::Var => {
+                    snapshot.globals.push((global.clone(), global.get()));
+                }
+                Extern::Table(table) => {
+                    let elements = (0..table.size())
+                        .map(|index| match table.get(index) {
+                            Some(Val::FuncRef(function)) => function,
+                            _ => None,
+                        })
+                        .collect();
+                    snapshot.tables.push((table.clone(), elements));
+                }
+                _ => {}
+            }
+        }
+
+        snapshot
+    }
+
+    /// Writes the snapshot back, returning false if the instance can't be restored.
+    fn restore(&self) -> bool {
+        let grown = self
+            .memories
+            .iter()
+            .any(|(memory, size, _)| memory.size() != *size)
+            || self
+                .tables
+                .iter()
+                .any(|(table, elements)| table.size() as usize != elements.len());
+        if grown {
+            return false;
+        }
+
+        for (memory, _, data) in &self.memories {
+            // SAFETY: the call using the instance has returned, nothing else accesses it.
+            unsafe { memory.data_unchecked_mut() }.copy_from_slice(data);
+        }
+        for (global, value) in &self.globals {
+            if global.set(value.clone()).is_err() {
+                return false;
+            }
+        }
+        for (table, elements) in &self.tables {
+            for (index, function) in elements.iter().enumerate() {
+                if table
+                    .set(index as u32, Val::FuncRef(function.clone()))
+                    .is_err()
+                {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::Value;
+
+    use super::{reuse_blocker, InstancePolicy};
+    use crate::module_store::{ModuleConfig, ModuleStore};
+
+    static WAT_COUNTER: &[u8] = br#"(module
+        (memory (export "memory") 1)
+        (global $calls (export "calls") (mut i32) (i32.const 0))
+        (func (export "count") (result i32)
+            (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+            (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+            (i32.add (global.get $calls) (i32.load (i32.const 0)))))"#;
+
+    #[test]
+    fn test_reused_instances_are_reset() -> anyhow::Result<()> {
+        let mut module_store = ModuleStore::default();
+        module_store.add_with_config(
+            "counter",
+            WAT_COUNTER,
+            false,
+            ModuleConfig {
+                instance_policy: InstancePolicy::Reuse,
+                pool_size: Some(1),
+                ..Default::default()
+            },
+        )?;
+        let package = module_store.get("counter").unwrap();
+        let pool = &package.pool;
+        assert_eq!(pool.stats().idle, 1);
+
+        for _ in 0..3 {
+            let lease = pool.acquire(package, &package.imports)?;
+            assert!(lease.warm);
+            let count = lease.instance.exports.get_function("count")?;
+            assert_eq!(count.call(&[])?[0], Value::I32(2));
+            pool.release(lease, true);
+        }
+
+        let stats = pool.stats();
+        assert_eq!(
+            (stats.cold_starts, stats.warm_starts, stats.idle),
+            (0, 3, 1)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fresh_instances_are_not_pooled() -> anyhow::Result<()> {
+        let mut module_store = ModuleStore::default();
+        module_store.add("counter", WAT_COUNTER, false)?;
+        let package = module_store.get("counter").unwrap();
+
+        let lease = package.pool.acquire(package, &package.imports)?;
+        assert!(!lease.warm);
+        package.pool.release(lease, true);
+        assert_eq!(package.pool.stats().idle, 0);
+
+        let hidden = wasmer::Module::new(
+            &wasmer::Store::default(),
+            r#"(module (global (mut i32) (i32.const 0)))"#,
+        )?;
+        assert!(reuse_blocker(&hidden).is_some());
+
+        Ok(())
+    }
+}
@@ -1 +1,9 @@
+pub mod canonical_abi;
 pub mod execute_module;
+pub mod filesystem;
+pub mod guest_memory;
+pub mod instance_pool;
+pub mod output;
+pub mod signature;
+pub mod values;
+pub mod wit;
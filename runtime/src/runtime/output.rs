@@ -0,0 +1,202 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+use wasmer_wasi::{FsError, VirtualFile};
+
+/// Bytes a WASI module may write to each of stdout and stderr per invocation, unless its
+/// config sets another limit.
+pub const DEFAULT_OUTPUT_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Output written by a module, forwarded as it is produced when the caller streams it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputChunk {
+    pub stream: OutputStream,
+    pub data: String,
+}
+
+/// In-memory stdout or stderr of one invocation. Clones share the buffer, so the caller
+/// keeps a handle while the WASI state owns the file. Writes past the limit are dropped.
+#[derive(Debug, Clone)]
+pub struct CapturedOutput {
+    stream: OutputStream,
+    limit: usize,
+    buffer: Arc<Mutex<Vec<u8>>>,
+    truncated: Arc<AtomicBool>,
+    sink: Option<UnboundedSender<OutputChunk>>,
+    /// The start of a character split across writes, held back from the sink until the next
+    /// write or flush.
+    pending: Arc<Mutex<Vec<u8>>>,
+}
+
+impl CapturedOutput {
+    pub fn new(
+        stream: OutputStream,
+        limit: usize,
+        sink: Option<UnboundedSender<OutputChunk>>,
+    ) -> Self {
+        Self {
+            stream,
+            limit,
+            buffer: Arc::default(),
+            truncated: Arc::default(),
+            sink,
+            pending: Arc::default(),
+        }
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.lock()).into_owned()
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+}
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buffer = self.buffer.lock();
+        let accepted = buf.len().min(self.limit.saturating_sub(buffer.len()));
+        if accepted < buf.len() {
+            self.truncated.store(true, Ordering::Relaxed);
+        }
+        buffer.extend_from_slice(&buf[..accepted]);
+
+        drop(buffer);
+
+        if self.sink.is_some() && accepted > 0 {
+            let mut pending = self.pending.lock();
+            pending.extend_from_slice(&buf[..accepted]);
+            let complete = pending.len() - incomplete_tail(&pending);
+            let data = pending.drain(..complete).collect::<Vec<_>>();
+            self.send(&data);
+        }
+
+        // Report everything as written so the guest doesn't retry once over the limit.
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let data = std::mem::take(&mut *self.pending.lock());
+        self.send(&data);
+        Ok(())
+    }
+}
+
+impl CapturedOutput {
+    fn send(&self, data: &[u8]) {
+        if let (Some(sink), false) = (&self.sink, data.is_empty()) {
+            // The receiver going away only means nobody is streaming anymore.
+            let _ = sink.send(OutputChunk {
+                stream: self.stream,
+                data: String::from_utf8_lossy(data).into_owned(),
+            });
+        }
+    }
+}
+
+/// Length of the UTF-8 sequence at the end of `bytes` that is cut short, if any.
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    for len in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - len];
+        // Skip continuation bytes back to the byte starting the sequence.
+        if byte & 0xc0 == 0x80 {
+            continue;
+        }
+        let width = match byte {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        return if width > len { len } else { 0 };
+    }
+    0
+}
+
+impl Read for CapturedOutput {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Seek for CapturedOutput {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::other("captured output is not seekable"))
+    }
+}
+
+impl VirtualFile for CapturedOutput {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+
+    fn last_modified(&self) -> u64 {
+        0
+    }
+
+    fn created_time(&self) -> u64 {
+        0
+    }
+
+    fn size(&self) -> u64 {
+        self.buffer.lock().len() as u64
+    }
+
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+
+    fn bytes_available(&self) -> Result<usize, FsError> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{CapturedOutput, OutputStream};
+
+    #[test]
+    fn test_streamed_characters_are_not_split() -> anyhow::Result<()> {
+        let (sink, mut chunks) = tokio::sync::mpsc::unbounded_channel();
+        let mut output = CapturedOutput::new(OutputStream::Stdout, 1024, Some(sink));
+
+        let text = "héllo €".as_bytes();
+        output.write_all(&text[..2])?;
+        output.write_all(&text[2..9])?;
+        output.write_all(&text[9..])?;
+        output.write_all(b"\xe2")?;
+        output.flush()?;
+
+        let mut streamed = Vec::new();
+        while let Ok(chunk) = chunks.try_recv() {
+            streamed.push(chunk.data);
+        }
+        assert_eq!(streamed, vec!["h", "éllo ", "€", "\u{fffd}"]);
+        assert_eq!(output.contents(), "héllo €\u{fffd}");
+
+        Ok(())
+    }
+}
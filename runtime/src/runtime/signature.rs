@@ -0,0 +1,301 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use wasmer::{ExternType, FunctionType, Module};
+
+use crate::{
+    module_store::ModulePackage,
+    runtime::{
+        execute_module::{ArgValues, WasmArg, WasmFunction, WasmType},
+        values::check_json_value,
+    },
+};
+
+/// Type of an export or import.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ExternInfo {
+    #[serde(rename_all = "camelCase")]
+    Function {
+        params: Vec<WasmType>,
+        results: Vec<WasmType>,
+    },
+    /// Sizes in 64KiB pages.
+    #[serde(rename_all = "camelCase")]
+    Memory {
+        minimum: u32,
+        maximum: Option<u32>,
+        shared: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Table {
+        element: WasmType,
+        minimum: u32,
+        maximum: Option<u32>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Global { value_type: WasmType, mutable: bool },
+}
+
+impl From<&ExternType> for ExternInfo {
+    fn from(ty: &ExternType) -> Self {
+        match ty {
+            ExternType::Function(function) => ExternInfo::Function {
+                params: function.params().iter().map(|&ty| ty.into()).collect(),
+                results: function.results().iter().map(|&ty| ty.into()).collect(),
+            },
+            ExternType::Memory(memory) => ExternInfo::Memory {
+                minimum: memory.minimum.0,
+                maximum: memory.maximum.map(|pages| pages.0),
+                shared: memory.shared,
+            },
+            ExternType::Table(table) => ExternInfo::Table {
+                element: table.ty.into(),
+                minimum: table.minimum,
+                maximum: table.maximum,
+            },
+            ExternType::Global(global) => ExternInfo::Global {
+                value_type: global.ty.into(),
+                mutable: global.mutability.is_mutable(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub ty: ExternInfo,
+}
+
+/// What an import is satisfied by when the module is instantiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportResolution {
+    Wasi,
+    /// An export of another registered module.
+    Module,
+    /// Nothing provides it, instantiation will fail.
+    Unresolved,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportInfo {
+    pub module: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub ty: ExternInfo,
+    pub resolution: ImportResolution,
+}
+
+pub fn exports(package: &ModulePackage) -> Vec<ExportInfo> {
+    package
+        .module
+        .exports()
+        .map(|export| ExportInfo {
+            name: export.name().to_owned(),
+            ty: export.ty().into(),
+        })
+        .collect()
+}
+
+pub fn imports(package: &ModulePackage) -> Vec<ImportInfo> {
+    package
+        .module
+        .imports()
+        .map(|import| {
+            let resolution = if package.dependencies.contains_key(import.module()) {
+                ImportResolution::Module
+            } else if package.wasi && import.module().starts_with("wasi_") {
+                ImportResolution::Wasi
+            } else {
+                ImportResolution::Unresolved
+            };
+
+            ImportInfo {
+                module: import.module().to_owned(),
+                name: import.name().to_owned(),
+                ty: import.ty().into(),
+                resolution,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    FunctionNotFound(String),
+    /// The export exists but isn't a function.
+    NotAFunction(String),
+    /// The request args don't match the function params. Strings and bytes count as two
+    /// `I32`s.
+    ArgumentMismatch {
+        function: String,
+        expected: Vec<WasmType>,
+        found: Vec<WasmType>,
+    },
+    ArityMismatch {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    /// `param` is the name or position of the param the value doesn't fit.
+    InvalidArgument {
+        function: String,
+        param: String,
+        reason: String,
+    },
+    /// Named values need a WIT interface describing the function.
+    UnnamedParams(String),
+    /// The request passes both `args` and `values`.
+    MixedArguments(String),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::FunctionNotFound(name) => write!(f, "function not found: {}", name),
+            SignatureError::NotAFunction(name) => write!(f, "export {} is not a function", name),
+            SignatureError::ArgumentMismatch {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} takes {:?}, the request passes {:?}",
+                function, expected, found
+            ),
+            SignatureError::ArityMismatch {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} takes {} arguments, the request passes {}",
+                function, expected, found
+            ),
+            SignatureError::InvalidArgument {
+                function,
+                param,
+                reason,
+            } => write!(f, "invalid argument {} of {}: {}", param, function, reason),
+            SignatureError::UnnamedParams(function) => write!(
+                f,
+                "{} has no param names, pass its arguments as an array",
+                function
+            ),
+            SignatureError::MixedArguments(function) => {
+                write!(f, "pass either args or values to {}, not both", function)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Checks the request args against the exported function before anything is instantiated.
+pub fn check_args(module: &Module, function: &WasmFunction) -> Result<(), SignatureError> {
+    let ty = function_type(module, &function.name)?;
+
+    let expected = ty.params().iter().map(|&ty| ty.into()).collect::<Vec<_>>();
+    let found = function
+        .args
+        .iter()
+        .flat_map(|arg| match arg.arg_type.in_memory() {
+            true => vec![WasmType::I32; 2],
+            false => vec![arg.arg_type],
+        })
+        .collect::<Vec<_>>();
+    if expected != found {
+        return Err(SignatureError::ArgumentMismatch {
+            function: function.name.clone(),
+            expected,
+            found,
+        });
+    }
+
+    Ok(())
+}
+
+/// Turns the `values` of the request into `args` typed by the function params, rejecting
+/// values that don't fit them.
+pub fn infer_args(module: &Module, function: &mut WasmFunction) -> Result<(), SignatureError> {
+    if function.values.is_empty() {
+        return Ok(());
+    }
+    if !function.args.is_empty() {
+        return Err(SignatureError::MixedArguments(function.name.clone()));
+    }
+
+    let ty = function_type(module, &function.name)?;
+    let values = positional(std::mem::take(&mut function.values), &function.name, None)?;
+    if values.len() != ty.params().len() {
+        return Err(SignatureError::ArityMismatch {
+            function: function.name.clone(),
+            expected: ty.params().len(),
+            found: values.len(),
+        });
+    }
+
+    for (index, (value, &ty)) in values.into_iter().zip(ty.params()).enumerate() {
+        check_json_value(ty, &value).map_err(|err| SignatureError::InvalidArgument {
+            function: function.name.clone(),
+            param: index.to_string(),
+            reason: err.to_string(),
+        })?;
+        function.args.push(WasmArg {
+            value,
+            arg_type: ty.into(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Orders `values` by the param `names` of the function, which only functions of a WIT
+/// interface have.
+pub fn positional(
+    values: ArgValues,
+    function: &str,
+    names: Option<&[&str]>,
+) -> Result<Vec<Json>, SignatureError> {
+    let mut values = match (values, names) {
+        (ArgValues::Positional(values), _) => return Ok(values),
+        (ArgValues::Named(values), Some(_)) => values,
+        (ArgValues::Named(_), None) => {
+            return Err(SignatureError::UnnamedParams(function.to_owned()))
+        }
+    };
+    let invalid = |param: &str, reason: &str| SignatureError::InvalidArgument {
+        function: function.to_owned(),
+        param: param.to_owned(),
+        reason: reason.to_owned(),
+    };
+
+    let ordered = names
+        .unwrap_or_default()
+        .iter()
+        .map(|&name| values.remove(name).ok_or_else(|| invalid(name, "missing")))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(name) = values.keys().next() {
+        return Err(invalid(name, "no such param"));
+    }
+
+    Ok(ordered)
+}
+
+fn function_type(module: &Module, name: &str) -> Result<FunctionType, SignatureError> {
+    let export = module
+        .exports()
+        .find(|export| export.name() == name)
+        .ok_or_else(|| SignatureError::FunctionNotFound(name.to_owned()))?;
+
+    match export.ty() {
+        ExternType::Function(ty) => Ok(ty.clone()),
+        _ => Err(SignatureError::NotAFunction(name.to_owned())),
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::{anyhow, bail, Context};
+use serde_json::{Number, Value as Json};
+use wasmer::{Export, Exportable, ExternRef, Function, Instance, Type, Value};
+
+/// Opaque value the host hands to a module as an `externref`. Requests and responses refer
+/// to it by its number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostHandle(pub u64);
+
+/// Parses an argument written as text:
+/// - integers as usual,
+/// - floats as usual, as `inf`, `nan` or `nan:0x..` like the text format, or as their bit
+///   pattern `0x..`,
+/// - `v128` as `0x` followed by up to 32 hex digits, a decimal number or lanes like
+///   `i32x4 1 2 3 4` (lane 0 first),
+/// - `externref` as `null` or a host handle number,
+/// - `funcref` as `null` or the name of a function the instance exports.
+pub fn parse_value(ty: Type, value: &str, instance: &Instance) -> anyhow::Result<Value> {
+    parse_text(ty, value, Some(instance))
+}
+
+/// Like [`parse_value`], also taking numbers as JSON numbers and null references as `null`.
+pub fn parse_json_value(ty: Type, value: &Json, instance: &Instance) -> anyhow::Result<Value> {
+    parse_json(ty, value, Some(instance))
+}
+
+/// Checks an argument the way [`parse_json_value`] reads it, before there is an instance to
+/// look function references up in.
+pub fn check_json_value(ty: Type, value: &Json) -> anyhow::Result<()> {
+    parse_json(ty, value, None).map(drop)
+}
+
+/// Reads a number the way [`parse_json_value`] does, for types that don't need an instance.
+pub fn parse_json_number(ty: Type, value: &Json) -> anyhow::Result<Value> {
+    match ty {
+        Type::I32 | Type::I64 | Type::F32 | Type::F64 => parse_json(ty, value, None),
+        _ => bail!("{} is not a number type", ty),
+    }
+}
+
+fn parse_text(ty: Type, value: &str, instance: Option<&Instance>) -> anyhow::Result<Value> {
+    let value = value.trim();
+
+    Ok(match ty {
+        Type::I32 => Value::I32(value.parse()?),
+        Type::I64 => Value::I64(value.parse()?),
+        Type::F32 => Value::F32(f32::from_bits(parse_float(value, 32, 23)? as u32)),
+        Type::F64 => Value::F64(f64::from_bits(parse_float(value, 64, 52)?)),
+        Type::V128 => Value::V128(parse_v128(value)?),
+        Type::ExternRef => match value {
+            "null" => Value::ExternRef(ExternRef::null()),
+            handle => {
+                let handle = handle
+                    .parse()
+                    .with_context(|| format!("{} is not null or a host handle", handle))?;
+                Value::ExternRef(ExternRef::new(HostHandle(handle)))
+            }
+        },
+        Type::FuncRef => match (value, instance) {
+            ("null", _) | (_, None) => Value::FuncRef(None),
+            (name, Some(instance)) => {
+                let function = instance
+                    .exports
+                    .get_function(name)
+                    .with_context(|| format!("funcref {} is not an exported function", name))?;
+                Value::FuncRef(Some(function.clone()))
+            }
+        },
+    })
+}
+
+fn parse_json(ty: Type, value: &Json, instance: Option<&Instance>) -> anyhow::Result<Value> {
+    let number = match value {
+        Json::String(text) => return parse_text(ty, text, instance),
+        Json::Null => return parse_text(ty, "null", instance),
+        Json::Number(number) => number,
+        other => bail!("expected a number or a string, found {}", other),
+    };
+    let out_of_range = || anyhow!("{} is out of range for {}", number, ty);
+
+    Ok(match ty {
+        Type::I32 => Value::I32(
+            number
+                .as_i64()
+                .and_then(|int| i32::try_from(int).ok())
+                .ok_or_else(out_of_range)?,
+        ),
+        Type::I64 => Value::I64(number.as_i64().ok_or_else(out_of_range)?),
+        Type::F32 => Value::F32(number.as_f64().ok_or_else(out_of_range)? as f32),
+        Type::F64 => Value::F64(number.as_f64().ok_or_else(out_of_range)?),
+        _ => return parse_text(ty, &number.to_string(), instance),
+    })
+}
+
+/// Integers past this magnitude are written as strings, JSON parsers commonly read numbers
+/// as doubles and would round them.
+pub const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// A result as JSON: integers and finite floats as numbers, everything else as the text
+/// [`parse_value`] reads back, null references as `null`. With `raw_bits` floats are written
+/// as their bit pattern.
+pub fn to_json(value: &Value, raw_bits: bool) -> Json {
+    match value {
+        Value::I32(int) => Json::from(*int),
+        Value::I64(int) if int.unsigned_abs() <= MAX_SAFE_INTEGER as u64 => Json::from(*int),
+        Value::F32(float) if raw_bits => Json::String(format!("{:#010x}", float.to_bits())),
+        Value::F64(float) if raw_bits => Json::String(format!("{:#018x}", float.to_bits())),
+        // Going through the shortest representation keeps 0.1f32 from turning into
+        // 0.10000000149011612.
+        Value::F32(float) if float.is_finite() => float_to_json(float.to_string().parse().unwrap()),
+        Value::F64(float) if float.is_finite() => float_to_json(*float),
+        Value::F32(float) => Json::String(format_special(float.to_bits() as u64, 32, 23)),
+        Value::F64(float) => Json::String(format_special(float.to_bits(), 64, 52)),
+        Value::ExternRef(reference) if reference.is_null() => Json::Null,
+        Value::FuncRef(None) => Json::Null,
+        other => Json::String(format_value(other)),
+    }
+}
+
+/// Writes a value the way [`parse_value`] reads it back. Function references are written
+/// by [`funcref_name`], which needs the instance.
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::V128(bits) => format!("{:#034x}", bits),
+        Value::ExternRef(reference) if reference.is_null() => "null".to_owned(),
+        Value::ExternRef(reference) => match reference.downcast::<HostHandle>() {
+            Some(HostHandle(handle)) => handle.to_string(),
+            None => "externref".to_owned(),
+        },
+        Value::FuncRef(None) => "null".to_owned(),
+        other => other.to_string(),
+    }
+}
+
+/// Name of the export `function` refers to, `None` for null references. Functions the
+/// instance doesn't export are written as `anonymous`.
+pub fn funcref_name(function: Option<&Function>, instance: &Instance) -> Option<String> {
+    let address = code_address(function?);
+    let name = instance
+        .exports
+        .iter()
+        .functions()
+        .find(|(_, export)| code_address(export) == address)
+        .map_or("anonymous", |(name, _)| name.as_str());
+
+    Some(name.to_owned())
+}
+
+/// Functions coming back from wasm are new handles, only their code tells them apart.
+fn code_address(function: &Function) -> usize {
+    match function.to_export() {
+        Export::Function(export) => export.vm_function.address as usize,
+        _ => unreachable!("functions export functions"),
+    }
+}
+
+fn float_to_json(float: f64) -> Json {
+    Number::from_f64(float).map_or(Json::Null, Json::Number)
+}
+
+/// Writes an infinity or NaN of a float `width` bits wide with `mantissa` payload bits.
+fn format_special(bits: u64, width: u32, mantissa: u32) -> String {
+    let sign = if bits >> (width - 1) & 1 == 1 {
+        "-"
+    } else {
+        ""
+    };
+    let payload = bits & ((1 << mantissa) - 1);
+
+    match payload {
+        0 => format!("{}inf", sign),
+        payload if payload == 1 << (mantissa - 1) => format!("{}nan", sign),
+        payload => format!("{}nan:{:#x}", sign, payload),
+    }
+}
+
+/// Reads the bits of a float `width` bits wide with `mantissa` payload bits.
+fn parse_float(text: &str, width: u32, mantissa: u32) -> anyhow::Result<u64> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        let bits = u64::from_str_radix(hex, 16).context("invalid float bit pattern")?;
+        if width < 64 && bits >> width != 0 {
+            bail!("{} does not fit {} bits", text, width);
+        }
+        return Ok(bits);
+    }
+
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (1 << (width - 1), unsigned),
+        None => (0, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let exponent = ((1 << (width - 1)) - 1) & !((1 << mantissa) - 1);
+    let payload = match unsigned {
+        "inf" | "infinity" => Some(0),
+        "nan" => Some(1 << (mantissa - 1)),
+        other => match other.strip_prefix("nan:0x") {
+            Some(hex) => {
+                let payload = u64::from_str_radix(hex, 16).context("invalid NaN payload")?;
+                if payload == 0 || payload >> mantissa != 0 {
+                    bail!("{} is not a NaN payload of {} bits", hex, mantissa);
+                }
+                Some(payload)
+            }
+            None => None,
+        },
+    };
+
+    Ok(match (payload, width) {
+        (Some(payload), _) => sign | exponent | payload,
+        (None, 32) => text.parse::<f32>()?.to_bits() as u64,
+        (None, _) => text.parse::<f64>()?.to_bits(),
+    })
+}
+
+fn parse_v128(value: &str) -> anyhow::Result<u128> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        return u128::from_str_radix(hex, 16).context("invalid v128 hex");
+    }
+
+    let mut tokens = value.split_whitespace();
+    let shape = tokens.next().unwrap_or_default();
+    let lanes = tokens.collect::<Vec<_>>();
+    if lanes.is_empty() {
+        return value.parse().context("invalid v128");
+    }
+
+    let (width, float) = match shape {
+        "i8x16" => (8, false),
+        "i16x8" => (16, false),
+        "i32x4" => (32, false),
+        "i64x2" => (64, false),
+        "f32x4" => (32, true),
+        "f64x2" => (64, true),
+        other => bail!("unknown v128 shape {}", other),
+    };
+    if lanes.len() != 128 / width {
+        bail!("{} takes {} lanes, got {}", shape, 128 / width, lanes.len());
+    }
+
+    let mut bits = 0u128;
+    for (index, lane) in lanes.into_iter().enumerate() {
+        let lane_bits = match (float, width) {
+            (true, 32) => lane.parse::<f32>()?.to_bits() as u128,
+            (true, _) => lane.parse::<f64>()?.to_bits() as u128,
+            // Lanes may be written signed or unsigned, like in the text format.
+            (false, _) => {
+                let int = lane.parse::<i128>()?;
+                let (min, max) = (-(1i128 << (width - 1)), (1i128 << width) - 1);
+                if !(min..=max).contains(&int) {
+                    return Err(anyhow!("{} does not fit a {} bit lane", lane, width));
+                }
+                int as u128 & ((1u128 << width) - 1)
+            }
+        };
+        bits |= lane_bits << (index * width);
+    }
+
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wasmer::{imports, Instance, Module, Type, Value};
+
+    use super::{format_value, parse_json_value, parse_value, to_json};
+
+    #[test]
+    fn test_parse_and_format_values() -> anyhow::Result<()> {
+        let store = wasmer::Store::default();
+        let module = Module::new(&store, r#"(module (func (export "nop")))"#)?;
+        let instance = Instance::new(&module, &imports! {})?;
+        let parse = |ty, value: &str| parse_value(ty, value, &instance);
+
+        let v128 = parse(Type::V128, "i32x4 1 2 3 -1")?;
+        assert_eq!(format_value(&v128), "0xffffffff000000030000000200000001");
+        assert_eq!(parse(Type::V128, &format_value(&v128))?, v128);
+        assert_eq!(
+            parse(Type::V128, "i8x16 255 0 0 0 0 0 0 0 0 0 0 0 0 0 0 1")?,
+            Value::V128(1 << 120 | 0xff)
+        );
+        assert!(parse(Type::V128, "i8x16 256 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0").is_err());
+        assert!(parse(Type::V128, "i32x4 1 2").is_err());
+
+        let nan = Value::F32(f32::from_bits(0xffc0_0001));
+        assert_eq!(to_json(&nan, false), json!("-nan:0x400001"));
+        assert_eq!(
+            parse(Type::F32, "-nan:0x400001")?.f32().unwrap().to_bits(),
+            0xffc0_0001
+        );
+        assert_eq!(to_json(&Value::F32(f32::NAN), false), json!("nan"));
+        assert_eq!(
+            to_json(&Value::F64(f64::NEG_INFINITY), false),
+            json!("-inf")
+        );
+        assert_eq!(parse(Type::F64, "-inf")?, Value::F64(f64::NEG_INFINITY));
+        assert_eq!(to_json(&Value::F32(0.1), false), json!(0.1));
+        assert_eq!(to_json(&Value::F32(0.1), true), json!("0x3dcccccd"));
+        assert_eq!(parse(Type::F32, "0x3dcccccd")?, Value::F32(0.1));
+        assert_eq!(
+            to_json(&Value::I64(1 << 53), false),
+            json!("9007199254740992")
+        );
+        assert_eq!(to_json(&Value::I64(-42), false), json!(-42));
+        assert_eq!(
+            to_json(&Value::I64(i64::MIN), false),
+            json!("-9223372036854775808")
+        );
+
+        let parse_json = |ty, value| parse_json_value(ty, &value, &instance);
+        assert_eq!(parse_json(Type::I32, json!(-7))?, Value::I32(-7));
+        assert_eq!(
+            parse_json(Type::I64, json!("9007199254740993"))?,
+            Value::I64((1 << 53) + 1)
+        );
+        assert_eq!(parse_json(Type::F64, json!(2.5))?, Value::F64(2.5));
+        assert!(parse_json(Type::I32, json!(1u64 << 40)).is_err());
+        assert!(parse_json(Type::I32, json!(1.5)).is_err());
+
+        assert_eq!(format_value(&parse(Type::ExternRef, "null")?), "null");
+        assert_eq!(format_value(&parse(Type::ExternRef, "42")?), "42");
+        assert!(parse(Type::ExternRef, "handle").is_err());
+
+        assert!(matches!(
+            parse(Type::FuncRef, "nop")?,
+            Value::FuncRef(Some(_))
+        ));
+        assert_eq!(parse(Type::FuncRef, "null")?, Value::FuncRef(None));
+        assert!(parse(Type::FuncRef, "missing").is_err());
+
+        Ok(())
+    }
+}
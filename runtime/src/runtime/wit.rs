@@ -0,0 +1,805 @@
+use std::{cell::RefCell, collections::HashMap, fmt};
+
+use wasmer::{FunctionType, Module, Type};
+
+/// Types of a WIT interface, with named types resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    S8,
+    S16,
+    S32,
+    S64,
+    Float32,
+    Float64,
+    Char,
+    String,
+    List(Box<WitType>),
+    Tuple(Vec<WitType>),
+    Record(Vec<(String, WitType)>),
+    /// Cases and their optional payload. `option` and `expected` are variants too.
+    Variant(Vec<(String, Option<WitType>)>),
+    Enum(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitFunction {
+    pub name: String,
+    pub params: Vec<(String, WitType)>,
+    pub result: Option<WitType>,
+}
+
+/// Functions a module exports, described in the `.wit` syntax of wit-bindgen 0.1, e.g.
+/// `regex-is-match: function(text: string, pattern: string) -> bool`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Interface {
+    pub functions: Vec<WitFunction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitError {
+    Syntax {
+        line: usize,
+        message: String,
+    },
+    UnknownType(String),
+    /// The type refers to itself, which the canonical ABI can't represent.
+    RecursiveType(String),
+    TooDeep,
+    TooLarge,
+    MissingExport(String),
+    SignatureMismatch {
+        function: String,
+        expected: FunctionType,
+        found: FunctionType,
+    },
+}
+
+impl fmt::Display for WitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WitError::Syntax { line, message } => write!(f, "wit line {}: {}", line, message),
+            WitError::UnknownType(name) => write!(f, "unknown wit type: {}", name),
+            WitError::RecursiveType(name) => write!(f, "wit type {} is recursive", name),
+            WitError::TooDeep => write!(
+                f,
+                "wit types are nested more than {} levels deep",
+                MAX_TYPE_DEPTH
+            ),
+            WitError::TooLarge => write!(
+                f,
+                "wit function types are made of more than {} types",
+                MAX_TYPE_SIZE
+            ),
+            WitError::MissingExport(name) => {
+                write!(f, "module does not export wit function {}", name)
+            }
+            WitError::SignatureMismatch {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "export {} has signature {}, its wit declaration needs {}",
+                function, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WitError {}
+
+impl Interface {
+    pub fn parse(source: &str) -> Result<Self, WitError> {
+        let tokens = tokenize(source)?;
+        let parser = Parser::new(&tokens)?;
+
+        let mut functions = Vec::new();
+        for &start in &parser.functions {
+            functions.push(parser.function(start)?);
+        }
+        // Also reports errors in types no function uses.
+        for name in parser.definitions.keys() {
+            parser.named(name, &mut Resolving::default())?;
+        }
+
+        Ok(Self { functions })
+    }
+
+    pub fn function(&self, name: &str) -> Option<&WitFunction> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    /// Checks that the module exports every function with its canonical ABI signature.
+    pub fn check(&self, module: &Module) -> Result<(), WitError> {
+        for function in &self.functions {
+            let found = module
+                .exports()
+                .functions()
+                .find(|export| export.name() == function.name)
+                .ok_or_else(|| WitError::MissingExport(function.name.clone()))?;
+
+            let expected = function.core_type();
+            if *found.ty() != expected {
+                return Err(WitError::SignatureMismatch {
+                    function: function.name.clone(),
+                    expected,
+                    found: found.ty().clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Types nested deeper than this are rejected, the canonical ABI walks them recursively.
+pub const MAX_TYPE_DEPTH: usize = 64;
+/// The most types a function's params and result may be made of, counting every use of a
+/// named type.
+pub const MAX_TYPE_SIZE: usize = 10_000;
+
+/// Params flattening to more core values than this are passed through memory.
+pub const MAX_FLAT_PARAMS: usize = 16;
+/// Results flattening to more core values than this are returned through memory.
+pub const MAX_FLAT_RESULTS: usize = 1;
+
+impl WitFunction {
+    pub fn flat_params(&self) -> Vec<Type> {
+        let mut flat = Vec::new();
+        for (_, ty) in &self.params {
+            ty.flatten(&mut flat);
+        }
+        flat
+    }
+
+    pub fn flat_results(&self) -> Vec<Type> {
+        let mut flat = Vec::new();
+        if let Some(ty) = &self.result {
+            ty.flatten(&mut flat);
+        }
+        flat
+    }
+
+    pub fn params_in_memory(&self) -> bool {
+        self.flat_params().len() > MAX_FLAT_PARAMS
+    }
+
+    pub fn results_in_memory(&self) -> bool {
+        self.flat_results().len() > MAX_FLAT_RESULTS
+    }
+
+    /// Signature of the core wasm export implementing the function.
+    pub fn core_type(&self) -> FunctionType {
+        let params = match self.params_in_memory() {
+            true => vec![Type::I32],
+            false => self.flat_params(),
+        };
+        let results = match self.results_in_memory() {
+            true => vec![Type::I32],
+            false => self.flat_results(),
+        };
+
+        FunctionType::new(params, results)
+    }
+
+    /// The params as a tuple, the way they are laid out when passed through memory.
+    pub fn params_tuple(&self) -> WitType {
+        WitType::Tuple(self.params.iter().map(|(_, ty)| ty.clone()).collect())
+    }
+}
+
+impl WitType {
+    pub fn size(&self) -> usize {
+        match self {
+            WitType::Bool | WitType::U8 | WitType::S8 => 1,
+            WitType::U16 | WitType::S16 => 2,
+            WitType::U32 | WitType::S32 | WitType::Float32 | WitType::Char => 4,
+            WitType::U64 | WitType::S64 | WitType::Float64 => 8,
+            WitType::String | WitType::List(_) => 8,
+            WitType::Tuple(_) | WitType::Record(_) => {
+                let end = self
+                    .fields()
+                    .fold(0, |offset, ty| align_to(offset, ty.align()) + ty.size());
+                align_to(end, self.align())
+            }
+            WitType::Variant(cases) => {
+                let payload = cases
+                    .iter()
+                    .filter_map(|(_, ty)| ty.as_ref())
+                    .map(WitType::size)
+                    .max()
+                    .unwrap_or(0);
+                align_to(self.payload_offset() + payload, self.align())
+            }
+            WitType::Enum(cases) => discriminant_size(cases.len()),
+        }
+    }
+
+    pub fn align(&self) -> usize {
+        match self {
+            WitType::String | WitType::List(_) => 4,
+            WitType::Tuple(_) | WitType::Record(_) => {
+                self.fields().map(WitType::align).max().unwrap_or(1)
+            }
+            WitType::Variant(cases) => cases
+                .iter()
+                .filter_map(|(_, ty)| ty.as_ref())
+                .map(WitType::align)
+                .fold(discriminant_size(cases.len()), usize::max),
+            _ => self.size(),
+        }
+    }
+
+    /// Fields of a tuple or record, in order.
+    pub fn fields(&self) -> Box<dyn Iterator<Item = &WitType> + '_> {
+        match self {
+            WitType::Tuple(types) => Box::new(types.iter()),
+            WitType::Record(fields) => Box::new(fields.iter().map(|(_, ty)| ty)),
+            _ => Box::new(std::iter::empty()),
+        }
+    }
+
+    /// Offset of a variant's payload from the start of the variant.
+    pub fn payload_offset(&self) -> usize {
+        match self {
+            WitType::Variant(cases) => {
+                let payload_align = cases
+                    .iter()
+                    .filter_map(|(_, ty)| ty.as_ref())
+                    .map(WitType::align)
+                    .max()
+                    .unwrap_or(1);
+                align_to(discriminant_size(cases.len()), payload_align)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Appends the core wasm values this type is passed as.
+    pub fn flatten(&self, flat: &mut Vec<Type>) {
+        match self {
+            WitType::Bool
+            | WitType::U8
+            | WitType::U16
+            | WitType::U32
+            | WitType::S8
+            | WitType::S16
+            | WitType::S32
+            | WitType::Char
+            | WitType::Enum(_) => flat.push(Type::I32),
+            WitType::U64 | WitType::S64 => flat.push(Type::I64),
+            WitType::Float32 => flat.push(Type::F32),
+            WitType::Float64 => flat.push(Type::F64),
+            WitType::String | WitType::List(_) => flat.extend([Type::I32, Type::I32]),
+            WitType::Tuple(_) | WitType::Record(_) => {
+                for field in self.fields() {
+                    field.flatten(flat);
+                }
+            }
+            WitType::Variant(cases) => {
+                flat.push(Type::I32);
+                let mut payload: Vec<Type> = Vec::new();
+                for ty in cases.iter().filter_map(|(_, ty)| ty.as_ref()) {
+                    let mut case = Vec::new();
+                    ty.flatten(&mut case);
+                    for (index, ty) in case.into_iter().enumerate() {
+                        match payload.get_mut(index) {
+                            Some(joined) => *joined = join(*joined, ty),
+                            None => payload.push(ty),
+                        }
+                    }
+                }
+                flat.extend(payload);
+            }
+        }
+    }
+}
+
+pub fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+pub fn discriminant_size(cases: usize) -> usize {
+    match cases {
+        0..=0x100 => 1,
+        0x101..=0x10000 => 2,
+        _ => 4,
+    }
+}
+
+/// The core type able to hold either of two variant payloads.
+fn join(a: Type, b: Type) -> Type {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (Type::I32, Type::F32) | (Type::F32, Type::I32) => Type::I32,
+        _ => Type::I64,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Punct(&'static str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, WitError> {
+    const PUNCTS: [&str; 10] = ["->", ":", ",", "(", ")", "{", "}", "<", ">", "="];
+
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let mut rest = line.split("//").next().unwrap_or_default().trim_start();
+        while !rest.is_empty() {
+            if let Some(punct) = PUNCTS.iter().find(|punct| rest.starts_with(**punct)) {
+                tokens.push((line_number, Token::Punct(punct)));
+                rest = &rest[punct.len()..];
+            } else {
+                // `%` escapes identifiers that would be keywords.
+                let word = rest.trim_start_matches('%');
+                let len = word
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                    .unwrap_or(word.len());
+                if len == 0 {
+                    return Err(WitError::Syntax {
+                        line: line_number,
+                        message: format!("unexpected character {:?}", &word[..1]),
+                    });
+                }
+                tokens.push((line_number, Token::Ident(word[..len].to_owned())));
+                rest = &word[len..];
+            }
+            rest = rest.trim_start();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Finds the definitions in a first pass, so types can be used before they are defined,
+/// then parses them on demand.
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    definitions: HashMap<String, usize>,
+    functions: Vec<usize>,
+    /// Named types parsed so far, with their depth and size.
+    resolved: RefCell<HashMap<String, (WitType, usize, usize)>>,
+}
+
+/// Bookkeeping while resolving the types of one item.
+#[derive(Default)]
+struct Resolving {
+    /// Named types being resolved, innermost last.
+    names: Vec<String>,
+    /// How deeply the current type is nested, and the deepest any type got.
+    depth: usize,
+    deepest: usize,
+    /// Types parsed so far, counting every use of a named type.
+    size: usize,
+}
+
+/// Position in the token stream while parsing one item.
+struct Cursor<'p, 'a> {
+    parser: &'p Parser<'a>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [(usize, Token)]) -> Result<Self, WitError> {
+        let mut parser = Parser {
+            tokens,
+            definitions: HashMap::new(),
+            functions: Vec::new(),
+            resolved: RefCell::default(),
+        };
+
+        let mut cursor = Cursor {
+            parser: &parser,
+            pos: 0,
+        };
+        let mut definitions = HashMap::new();
+        let mut functions = Vec::new();
+        while cursor.pos < tokens.len() {
+            let start = cursor.pos;
+            let word = cursor.ident()?;
+            match word.as_str() {
+                "record" | "variant" | "enum" | "type" => {
+                    let name = cursor.ident()?;
+                    if definitions.insert(name.clone(), start).is_some() {
+                        return Err(cursor.error(format!("type {} is defined twice", name)));
+                    }
+                    cursor.skip_item()?;
+                }
+                "flags" | "union" | "resource" | "use" => {
+                    return Err(cursor.error(format!("{} is not supported", word)))
+                }
+                _ => {
+                    cursor.expect(":")?;
+                    functions.push(start);
+                    cursor.skip_item()?;
+                }
+            }
+        }
+
+        parser.definitions = definitions;
+        parser.functions = functions;
+        Ok(parser)
+    }
+
+    fn function(&self, start: usize) -> Result<WitFunction, WitError> {
+        let mut cursor = Cursor {
+            parser: self,
+            pos: start,
+        };
+        let name = cursor.ident()?;
+        cursor.expect(":")?;
+        cursor.keyword("function")?;
+        cursor.expect("(")?;
+
+        let mut params = Vec::new();
+        let mut resolving = Resolving::default();
+        while !cursor.eat(")") {
+            let param = cursor.ident()?;
+            cursor.expect(":")?;
+            params.push((param, cursor.ty(&mut resolving)?));
+            if !cursor.eat(",") {
+                cursor.expect(")")?;
+                break;
+            }
+        }
+
+        let result = match cursor.eat("->") {
+            true => Some(cursor.ty(&mut resolving)?),
+            false => None,
+        };
+
+        Ok(WitFunction {
+            name,
+            params,
+            result,
+        })
+    }
+
+    fn named(&self, name: &str, resolving: &mut Resolving) -> Result<WitType, WitError> {
+        if let Some((ty, depth, size)) = self.resolved.borrow().get(name) {
+            resolving.add(*depth, *size)?;
+            return Ok(ty.clone());
+        }
+
+        let start = *self
+            .definitions
+            .get(name)
+            .ok_or_else(|| WitError::UnknownType(name.to_owned()))?;
+        if resolving.names.iter().any(|other| other == name) {
+            return Err(WitError::RecursiveType(name.to_owned()));
+        }
+        resolving.names.push(name.to_owned());
+        let (outer_deepest, outer_size) = (resolving.deepest, resolving.size);
+        resolving.deepest = resolving.depth;
+
+        let mut cursor = Cursor {
+            parser: self,
+            pos: start,
+        };
+        let kind = cursor.ident()?;
+        cursor.ident()?;
+        let ty = match kind.as_str() {
+            "type" => {
+                cursor.expect("=")?;
+                cursor.ty(resolving)?
+            }
+            "record" => {
+                let mut fields = Vec::new();
+                cursor.list("{", "}", |cursor| {
+                    let field = cursor.ident()?;
+                    cursor.expect(":")?;
+                    fields.push((field, cursor.ty(resolving)?));
+                    Ok(())
+                })?;
+                WitType::Record(fields)
+            }
+            "variant" => {
+                let mut cases = Vec::new();
+                cursor.list("{", "}", |cursor| {
+                    let case = cursor.ident()?;
+                    let payload = match cursor.eat("(") {
+                        true => {
+                            let ty = cursor.ty(resolving)?;
+                            cursor.expect(")")?;
+                            Some(ty)
+                        }
+                        false => None,
+                    };
+                    cases.push((case, payload));
+                    Ok(())
+                })?;
+                WitType::Variant(cases)
+            }
+            _ => {
+                let mut cases = Vec::new();
+                cursor.list("{", "}", |cursor| {
+                    cases.push(cursor.ident()?);
+                    Ok(())
+                })?;
+                WitType::Enum(cases)
+            }
+        };
+
+        resolving.names.pop();
+        let (depth, size) = (
+            resolving.deepest - resolving.depth,
+            resolving.size - outer_size,
+        );
+        resolving.deepest = resolving.deepest.max(outer_deepest);
+        self.resolved
+            .borrow_mut()
+            .insert(name.to_owned(), (ty.clone(), depth, size));
+
+        Ok(ty)
+    }
+}
+
+impl Resolving {
+    /// Accounts for a type `depth` levels deep and made of `size` types, used at the current
+    /// depth.
+    fn add(&mut self, depth: usize, size: usize) -> Result<(), WitError> {
+        self.deepest = self.deepest.max(self.depth + depth);
+        self.size += size;
+        if self.deepest > MAX_TYPE_DEPTH {
+            return Err(WitError::TooDeep);
+        }
+        if self.size > MAX_TYPE_SIZE {
+            return Err(WitError::TooLarge);
+        }
+        Ok(())
+    }
+}
+
+impl Cursor<'_, '_> {
+    fn peek(&self) -> Option<&Token> {
+        self.parser.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn error(&self, message: String) -> WitError {
+        let line = self
+            .parser
+            .tokens
+            .get(self.pos.min(self.parser.tokens.len().saturating_sub(1)))
+            .map_or(0, |(line, _)| *line);
+        WitError::Syntax { line, message }
+    }
+
+    fn ident(&mut self) -> Result<String, WitError> {
+        match self.peek() {
+            Some(Token::Ident(ident)) => {
+                let ident = ident.clone();
+                self.pos += 1;
+                Ok(ident)
+            }
+            other => Err(self.error(format!("expected a name, found {:?}", other))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), WitError> {
+        match self.ident()? {
+            word if word == keyword => Ok(()),
+            word => Err(self.error(format!("expected {}, found {}", keyword, word))),
+        }
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        match self.peek() {
+            Some(Token::Punct(found)) if *found == punct => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), WitError> {
+        match self.eat(punct) {
+            true => Ok(()),
+            false => Err(self.error(format!("expected `{}`, found {:?}", punct, self.peek()))),
+        }
+    }
+
+    /// Parses `open item, item, ... close`, allowing a trailing comma.
+    fn list(
+        &mut self,
+        open: &str,
+        close: &str,
+        mut item: impl FnMut(&mut Self) -> Result<(), WitError>,
+    ) -> Result<(), WitError> {
+        self.expect(open)?;
+        while !self.eat(close) {
+            item(self)?;
+            if !self.eat(",") {
+                return self.expect(close);
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves past the rest of the current item, which ends where the next one starts.
+    fn skip_item(&mut self) -> Result<(), WitError> {
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punct("(" | "{" | "<") => depth += 1,
+                Token::Punct(")" | "}" | ">") => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or_else(|| self.error("unbalanced brackets".to_owned()))?
+                }
+                _ if depth == 0 && self.starts_item() => return Ok(()),
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// Items are definitions like `record name` or functions like `name: function`.
+    fn starts_item(&self) -> bool {
+        let token = |offset: usize| {
+            self.parser
+                .tokens
+                .get(self.pos + offset)
+                .map(|(_, token)| token)
+        };
+        let ident = |offset: usize| match token(offset) {
+            Some(Token::Ident(word)) => Some(word.as_str()),
+            _ => None,
+        };
+
+        match (ident(0), token(1), ident(2)) {
+            (Some(_), Some(Token::Punct(":")), Some("function")) => true,
+            (Some(keyword), Some(Token::Ident(_)), _) => matches!(
+                keyword,
+                "record" | "variant" | "enum" | "type" | "flags" | "union" | "resource" | "use"
+            ),
+            _ => false,
+        }
+    }
+
+    fn ty(&mut self, resolving: &mut Resolving) -> Result<WitType, WitError> {
+        let name = self.ident()?;
+        resolving.depth += 1;
+        resolving.add(0, 1)?;
+        let ty = match name.as_str() {
+            "bool" => WitType::Bool,
+            "u8" => WitType::U8,
+            "u16" => WitType::U16,
+            "u32" => WitType::U32,
+            "u64" => WitType::U64,
+            "s8" => WitType::S8,
+            "s16" => WitType::S16,
+            "s32" => WitType::S32,
+            "s64" => WitType::S64,
+            "float32" => WitType::Float32,
+            "float64" => WitType::Float64,
+            "char" => WitType::Char,
+            "string" => WitType::String,
+            "list" => {
+                self.expect("<")?;
+                let element = self.ty(resolving)?;
+                self.expect(">")?;
+                // Their length would be unbounded by the memory holding them.
+                if element.size() == 0 {
+                    return Err(self.error("list elements can't be zero-sized".to_owned()));
+                }
+                WitType::List(Box::new(element))
+            }
+            "option" => {
+                self.expect("<")?;
+                let some = self.ty(resolving)?;
+                self.expect(">")?;
+                WitType::Variant(vec![
+                    ("none".to_owned(), None),
+                    ("some".to_owned(), Some(some)),
+                ])
+            }
+            "expected" => {
+                self.expect("<")?;
+                let ok = self.optional_ty(resolving)?;
+                self.expect(",")?;
+                let err = self.optional_ty(resolving)?;
+                self.expect(">")?;
+                WitType::Variant(vec![("ok".to_owned(), ok), ("err".to_owned(), err)])
+            }
+            "tuple" => {
+                let mut types = Vec::new();
+                self.list("<", ">", |cursor| {
+                    types.push(cursor.ty(resolving)?);
+                    Ok(())
+                })?;
+                WitType::Tuple(types)
+            }
+            _ => self.parser.named(&name, resolving)?,
+        };
+        resolving.depth -= 1;
+
+        Ok(ty)
+    }
+
+    /// A type, or `_` for none.
+    fn optional_ty(&mut self, resolving: &mut Resolving) -> Result<Option<WitType>, WitError> {
+        match self.peek() {
+            Some(Token::Ident(word)) if word == "_" => {
+                self.pos += 1;
+                Ok(None)
+            }
+            _ => self.ty(resolving).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::{FunctionType, Type};
+
+    use super::{Interface, WitError, WitType};
+
+    #[test]
+    fn test_parse_interface() -> anyhow::Result<()> {
+        let interface = Interface::parse(include_str!(
+            "../../../binaries/emscripten/cpp/regex/regex-is-match.wit"
+        ))?;
+        let function = interface.function("regex-is-match").unwrap();
+        assert_eq!(function.result, Some(WitType::Bool));
+        assert_eq!(
+            function.core_type(),
+            FunctionType::new([Type::I32; 4], [Type::I32])
+        );
+
+        let interface = Interface::parse(
+            "// shapes
+            area: function(s: shape) -> option<float64>
+            variant shape { circle(float32), rect(point), empty }
+            record point { x: s32, y: s32 }",
+        )?;
+        let shape = &interface.functions[0].params[0].1;
+        assert_eq!(
+            (shape.size(), shape.align(), shape.payload_offset()),
+            (12, 4, 4)
+        );
+        assert_eq!(
+            interface.functions[0].core_type(),
+            FunctionType::new([Type::I32; 3], [Type::I32])
+        );
+
+        assert_eq!(
+            Interface::parse("f: function(a: missing)"),
+            Err(WitError::UnknownType("missing".to_owned()))
+        );
+        assert_eq!(
+            Interface::parse("record node { next: list<node> }"),
+            Err(WitError::RecursiveType("node".to_owned()))
+        );
+
+        assert!(matches!(
+            Interface::parse("f: function() -> list<tuple<>>"),
+            Err(WitError::Syntax { .. })
+        ));
+
+        let nested = format!(
+            "f: function(a: {}u8{})",
+            "list<".repeat(100),
+            ">".repeat(100)
+        );
+        assert_eq!(Interface::parse(&nested), Err(WitError::TooDeep));
+        // Every type doubles the size of the one before it.
+        let mut doubling = "f: function(a: t20)\ntype t0 = u8\n".to_owned();
+        for index in 1..=20 {
+            doubling += &format!("type t{} = tuple<t{1}, t{1}>\n", index, index - 1);
+        }
+        assert_eq!(Interface::parse(&doubling), Err(WitError::TooLarge));
+
+        Ok(())
+    }
+}
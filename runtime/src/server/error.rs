@@ -0,0 +1,141 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    limits::LimitError,
+    module_store::ModuleStoreError,
+    runtime::{
+        execute_module::ExecutionError, filesystem::MountError, signature::SignatureError,
+        wit::WitError,
+    },
+};
+
+/// JSON body returned by every route when a request fails.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub status: u16,
+    pub message: String,
+    /// Machine readable reason for failures clients may want to tell apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    pub code: Option<&'static str>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            code: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    pub fn module_not_found(name: &str) -> Self {
+        Self::not_found(format!("module not found: {}", name))
+    }
+
+    /// Maps errors coming out of the module store to a status code, falling back to 500.
+    pub fn from_store(err: anyhow::Error) -> Self {
+        if err.is::<wasmer::CompileError>()
+            || err.is::<LimitError>()
+            || err.is::<MountError>()
+            || err.is::<WitError>()
+        {
+            return Self::bad_request(err.to_string());
+        }
+
+        let status = match err.downcast_ref::<ModuleStoreError>() {
+            Some(ModuleStoreError::NotFound(_) | ModuleStoreError::VersionNotFound { .. }) => {
+                StatusCode::NOT_FOUND
+            }
+            Some(
+                ModuleStoreError::HasDependents { .. }
+                | ModuleStoreError::DependencyCycle(_)
+                | ModuleStoreError::DependentRebuild { .. },
+            ) => StatusCode::CONFLICT,
+            Some(
+                ModuleStoreError::InvalidName(_)
+                | ModuleStoreError::ReservedAlias(_)
+                | ModuleStoreError::UnresolvedImport { .. }
+                | ModuleStoreError::IncompatibleImport { .. }
+                | ModuleStoreError::CannotReuseInstances { .. },
+            ) => StatusCode::BAD_REQUEST,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        Self::new(status, err.to_string())
+    }
+
+    /// Maps errors raised while running a function; guest traps are the caller's fault.
+    pub fn from_execution(err: anyhow::Error) -> Self {
+        if let Some(LimitError::Instances { .. }) = err.downcast_ref::<LimitError>() {
+            return Self::new(StatusCode::TOO_MANY_REQUESTS, err.to_string())
+                .with_code("too_many_instances");
+        }
+
+        match err.downcast_ref::<SignatureError>() {
+            Some(SignatureError::FunctionNotFound(_)) => {
+                return Self::not_found(err.to_string()).with_code("function_not_found")
+            }
+            Some(_) => return Self::bad_request(err.to_string()).with_code("signature_mismatch"),
+            None => {}
+        }
+
+        match err.downcast_ref::<ExecutionError>() {
+            Some(ExecutionError::OutOfFuel { .. }) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+                    .with_code("out_of_fuel")
+            }
+            Some(ExecutionError::Timeout { .. }) => {
+                Self::new(StatusCode::GATEWAY_TIMEOUT, err.to_string()).with_code("timeout")
+            }
+            Some(ExecutionError::WasiOnly { .. }) => {
+                Self::bad_request(err.to_string()).with_code("wasi_required")
+            }
+            None => Self::bad_request(format!("{:?}", err)),
+        }
+    }
+}
+
+impl From<ApiError> for ErrorBody {
+    fn from(err: ApiError) -> Self {
+        ErrorBody {
+            status: err.status.as_u16(),
+            message: err.message,
+            code: err.code.map(str::to_owned),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(ErrorBody::from(self))).into_response()
+    }
+}
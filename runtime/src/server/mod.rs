@@ -1 +1,57 @@
+use axum::{
+    extract::Extension,
+    routing::{get, post, put},
+    Router,
+};
+
+use crate::ServerState;
+
+use self::routes::{
+    execute_function::{
+        call_function_handler, execute_function_handler, invoke_function_handler,
+        run_command_handler, stream_function_handler,
+    },
+    modules::{
+        delete_alias_handler, delete_module_handler, get_module_handler, list_modules_handler,
+        list_versions_handler, set_alias_handler,
+    },
+    register_function::register_function_handler,
+    register_node::{list_nodes, register_node},
+};
+
+pub mod error;
 pub mod routes;
+
+pub fn router(state: ServerState) -> Router {
+    Router::new()
+        .route("/register", post(register_function_handler))
+        .route("/exec", post(execute_function_handler))
+        .route(
+            "/modules",
+            get(list_modules_handler).post(register_function_handler),
+        )
+        .route(
+            "/modules/:name",
+            get(get_module_handler).delete(delete_module_handler),
+        )
+        .route("/modules/:name/versions", get(list_versions_handler))
+        .route("/modules/:name/run", post(run_command_handler))
+        .route(
+            "/modules/:name/aliases/:alias",
+            put(set_alias_handler).delete(delete_alias_handler),
+        )
+        .route(
+            "/modules/:name/functions/:function/invoke",
+            post(invoke_function_handler),
+        )
+        .route(
+            "/modules/:name/functions/:function/call",
+            post(call_function_handler),
+        )
+        .route(
+            "/modules/:name/functions/:function/stream",
+            post(stream_function_handler),
+        )
+        .route("/nodes", get(list_nodes).post(register_node))
+        .layer(Extension(state))
+}
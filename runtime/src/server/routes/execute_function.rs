@@ -1,33 +1,197 @@
-use axum::{extract::Extension, http::StatusCode, Json};
+use std::sync::Arc;
+
+use axum::{
+    body::{self, Body, Bytes},
+    extract::{Extension, Path},
+    http::header,
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 
 use crate::{
-    runtime::execute_module::{execute_function, ExecuteModuleRequest, WasmResult},
+    module_store::ModulePackage,
+    runtime::{
+        execute_module::{
+            execute_function, execute_function_streaming, ArgValues, ExecuteModuleRequest,
+            ExecuteModuleResponse, InvocationOptions, WasmArg, WasmFunction, WasmType,
+        },
+        output::OutputChunk,
+    },
+    server::error::{ApiError, ErrorBody},
     ServerState,
 };
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvokeFunctionPayload {
+    #[serde(default)]
+    pub args: Vec<WasmArg>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub results: Vec<WasmType>,
+    /// Arguments typed by the function signature instead of `args`.
+    #[serde(default, skip_serializing_if = "ArgValues::is_empty")]
+    pub values: ArgValues,
+    #[serde(flatten)]
+    pub options: InvocationOptions,
+}
+
+impl InvokeFunctionPayload {
+    fn into_request(self, module_name: String, function_name: String) -> ExecuteModuleRequest {
+        ExecuteModuleRequest {
+            module_name,
+            function: WasmFunction {
+                name: function_name,
+                args: self.args,
+                results: self.results,
+                values: self.values,
+            },
+            options: self.options,
+        }
+    }
+}
+
+/// One line of a streamed invocation: output as the module writes it, then either the
+/// result or the error that ended the call.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum StreamEvent {
+    Output(OutputChunk),
+    Result(ExecuteModuleResponse),
+    Error(ErrorBody),
+}
+
 pub async fn execute_function_handler(
     Extension(state): Extension<ServerState>,
     Json(payload): Json<ExecuteModuleRequest>,
-) -> Result<Json<Vec<WasmResult>>, (StatusCode, String)> {
-    println!("{:#?}", payload);
-    let module_store = state.module_store.lock().await;
-    let module_package = module_store
-        .get(&payload.module_name)
-        .ok_or((StatusCode::BAD_REQUEST, "module not found".to_owned()))?;
-
-    let result = execute_function(module_package, payload)
-        .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, String::from(format!("{:?}", e))))?;
+) -> Result<Json<ExecuteModuleResponse>, ApiError> {
+    let result = execute(&state, payload).await?;
+
+    Ok(Json(result))
+}
+
+pub async fn invoke_function_handler(
+    Extension(state): Extension<ServerState>,
+    Path((module_name, function_name)): Path<(String, String)>,
+    Json(payload): Json<InvokeFunctionPayload>,
+) -> Result<Json<ExecuteModuleResponse>, ApiError> {
+    let request = payload.into_request(module_name, function_name);
+    let result = execute(&state, request).await?;
+
+    Ok(Json(result))
+}
+
+/// Invokes a function with a bare array (or, for WIT functions, object) of arguments typed by
+/// its signature.
+pub async fn call_function_handler(
+    Extension(state): Extension<ServerState>,
+    Path((module_name, function_name)): Path<(String, String)>,
+    Json(values): Json<ArgValues>,
+) -> Result<Json<ExecuteModuleResponse>, ApiError> {
+    let payload = InvokeFunctionPayload {
+        args: vec![],
+        results: vec![],
+        values,
+        options: InvocationOptions::default(),
+    };
+    let result = execute(&state, payload.into_request(module_name, function_name)).await?;
+
+    Ok(Json(result))
+}
+
+/// Runs a WASI command module, reporting its exit code along with its output.
+pub async fn run_command_handler(
+    Extension(state): Extension<ServerState>,
+    Path(module_name): Path<String>,
+    Json(options): Json<InvocationOptions>,
+) -> Result<Json<ExecuteModuleResponse>, ApiError> {
+    let request = ExecuteModuleRequest::command(module_name, options);
+    let result = execute(&state, request).await?;
+
+    Ok(Json(result))
+}
+
+/// Invokes a function, streaming its WASI output as newline delimited [`StreamEvent`]s.
+pub async fn stream_function_handler(
+    Extension(state): Extension<ServerState>,
+    Path((module_name, function_name)): Path<(String, String)>,
+    Json(payload): Json<InvokeFunctionPayload>,
+) -> Result<Response, ApiError> {
+    let request = payload.into_request(module_name, function_name);
+    let raw_bits = request.options.raw_bits;
+    let module_package = lookup(&state, &request.module_name)?;
+
+    let (chunks, mut received) = mpsc::unbounded_channel();
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let mut execution = tokio::spawn(async move {
+            execute_function_streaming(module_package, request, chunks)
+                .await
+                .map(|output| ExecuteModuleResponse::new(output, raw_bits))
+        });
 
-    let result = result
-        .into_iter()
-        .map(|v| WasmResult {
-            result_type: v.ty(),
-            result: v.to_string(),
-        })
-        .collect::<Vec<_>>();
+        let last = loop {
+            tokio::select! {
+                Some(chunk) = received.recv() => {
+                    if sender.send_data(event_line(StreamEvent::Output(chunk))).await.is_err() {
+                        // The client went away, dropping the call interrupts the instance.
+                        execution.abort();
+                        return;
+                    }
+                }
+                result = &mut execution => {
+                    let result = result
+                        .map_err(anyhow::Error::from)
+                        .and_then(|result| result);
+                    break match result {
+                        Ok(response) => StreamEvent::Result(response),
+                        Err(err) => StreamEvent::Error(ApiError::from_execution(err).into()),
+                    };
+                }
+            }
+        };
 
-    println!("{:#?}", result);
+        while let Ok(chunk) = received.try_recv() {
+            let _ = sender
+                .send_data(event_line(StreamEvent::Output(chunk)))
+                .await;
+        }
+        let _ = sender.send_data(event_line(last)).await;
+    });
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(body::boxed(body))
+        .expect("valid response"))
+}
+
+fn event_line(event: StreamEvent) -> Bytes {
+    let mut line = serde_json::to_vec(&event).expect("events serialize");
+    line.push(b'\n');
+    line.into()
+}
+
+fn lookup(state: &ServerState, reference: &str) -> Result<Arc<ModulePackage>, ApiError> {
+    state
+        .module_store
+        .snapshot()
+        .get(reference)
+        .cloned()
+        .ok_or_else(|| ApiError::module_not_found(reference))
+}
+
+async fn execute(
+    state: &ServerState,
+    payload: ExecuteModuleRequest,
+) -> Result<ExecuteModuleResponse, ApiError> {
+    let module_package = lookup(state, &payload.module_name)?;
+    let raw_bits = payload.options.raw_bits;
+
+    let output = execute_function(module_package, payload)
+        .await
+        .map_err(ApiError::from_execution)?;
 
-    Ok(result.into())
+    Ok(ExecuteModuleResponse::new(output, raw_bits))
 }
@@ -1,2 +1,4 @@
 pub mod execute_function;
+pub mod modules;
 pub mod register_function;
+pub mod register_node;
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Extension, Path},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    module_store::{ModuleRef, ModuleVersion, ModuleVersionInfo},
+    runtime::{
+        instance_pool::InstanceStats,
+        signature::{self, ExportInfo, ImportInfo},
+    },
+    server::error::ApiError,
+    ServerState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleSummary {
+    pub name: String,
+    pub wasi: bool,
+    pub latest_version: u64,
+    pub aliases: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleInfo {
+    pub name: String,
+    pub version: u64,
+    pub hash: String,
+    pub wasi: bool,
+    pub aliases: BTreeMap<String, u64>,
+    pub exports: Vec<ExportInfo>,
+    pub imports: Vec<ImportInfo>,
+    pub instances: InstanceStats,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetAliasPayload {
+    pub version: u64,
+}
+
+impl ModuleInfo {
+    fn new(name: &str, version: &ModuleVersion, aliases: &BTreeMap<String, u64>) -> Self {
+        let package = &version.package;
+
+        Self {
+            name: name.to_owned(),
+            version: version.version,
+            hash: version.hash.clone(),
+            wasi: package.wasi,
+            aliases: aliases.clone(),
+            exports: signature::exports(package),
+            imports: signature::imports(package),
+            instances: package.pool.stats(),
+        }
+    }
+}
+
+pub async fn list_modules_handler(
+    Extension(state): Extension<ServerState>,
+) -> Json<Vec<ModuleSummary>> {
+    let module_store = state.module_store.snapshot();
+
+    let mut modules = module_store
+        .iter()
+        .map(|(name, entry)| ModuleSummary {
+            name: name.clone(),
+            wasi: entry.latest().package.wasi,
+            latest_version: entry.latest().version,
+            aliases: entry.aliases().clone(),
+        })
+        .collect::<Vec<_>>();
+    modules.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    Json(modules)
+}
+
+/// Describes one version of a module, `reference` being `name`, `name@version` or `name:alias`.
+pub async fn get_module_handler(
+    Extension(state): Extension<ServerState>,
+    Path(reference): Path<String>,
+) -> Result<Json<ModuleInfo>, ApiError> {
+    let module_store = state.module_store.snapshot();
+    let version = module_store
+        .get_version(&reference)
+        .ok_or_else(|| ApiError::module_not_found(&reference))?;
+    let name = ModuleRef::parse(&reference)
+        .expect("resolved references are valid")
+        .name;
+    let entry = module_store.entry(&name).expect("resolved module exists");
+
+    Ok(Json(ModuleInfo::new(&name, version, entry.aliases())))
+}
+
+pub async fn list_versions_handler(
+    Extension(state): Extension<ServerState>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<ModuleVersionInfo>>, ApiError> {
+    let module_store = state.module_store.snapshot();
+    let entry = module_store
+        .entry(&name)
+        .ok_or_else(|| ApiError::module_not_found(&name))?;
+
+    Ok(Json(entry.versions().map(|v| v.info(&name)).collect()))
+}
+
+pub async fn set_alias_handler(
+    Extension(state): Extension<ServerState>,
+    Path((name, alias)): Path<(String, String)>,
+    Json(payload): Json<SetAliasPayload>,
+) -> Result<&'static str, ApiError> {
+    state
+        .module_store
+        .update(move |module_store| module_store.set_alias(&name, &alias, payload.version))
+        .await
+        .map_err(ApiError::from_store)?;
+
+    Ok("OK")
+}
+
+pub async fn delete_alias_handler(
+    Extension(state): Extension<ServerState>,
+    Path((name, alias)): Path<(String, String)>,
+) -> Result<&'static str, ApiError> {
+    state
+        .module_store
+        .update(move |module_store| module_store.remove_alias(&name, &alias))
+        .await
+        .map_err(ApiError::from_store)?;
+
+    Ok("OK")
+}
+
+pub async fn delete_module_handler(
+    Extension(state): Extension<ServerState>,
+    Path(name): Path<String>,
+) -> Result<&'static str, ApiError> {
+    state
+        .module_store
+        .update(move |module_store| module_store.remove(&name).map(drop))
+        .await
+        .map_err(ApiError::from_store)?;
+
+    Ok("OK")
+}
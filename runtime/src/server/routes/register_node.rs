@@ -2,25 +2,46 @@ use std::net::SocketAddr;
 
 use axum::{
     extract::{ConnectInfo, Extension},
-    http::StatusCode,
     Json,
 };
-use chrono::Utc;
+use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::ServerState;
+use crate::{server::error::ApiError, ServerState};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegisterNode {
     name: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KnownNode {
+    pub addr: SocketAddr,
+    pub last_seen: NaiveDateTime,
+}
+
 pub async fn register_node(
     Extension(state): Extension<ServerState>,
-    Json(_payload): Json<RegisterNode>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> Result<&'static str, (StatusCode, String)> {
+    Json(_payload): Json<RegisterNode>,
+) -> Result<&'static str, ApiError> {
     let mut known_nodes = state.known_nodes.lock().await;
     known_nodes.insert(addr, Utc::now().naive_utc());
     Ok("OK")
 }
+
+pub async fn list_nodes(Extension(state): Extension<ServerState>) -> Json<Vec<KnownNode>> {
+    let known_nodes = state.known_nodes.lock().await;
+
+    let mut nodes = known_nodes
+        .iter()
+        .map(|(addr, last_seen)| KnownNode {
+            addr: *addr,
+            last_seen: *last_seen,
+        })
+        .collect::<Vec<_>>();
+    nodes.sort_unstable_by_key(|node| node.addr);
+
+    Json(nodes)
+}
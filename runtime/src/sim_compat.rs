@@ -1,17 +1,24 @@
 use std::{
+    any::Any,
     collections::HashMap,
     ffi::{CStr, CString},
+    fmt,
     os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        PoisonError,
+    },
 };
 
 use crossbeam::sync::ShardedLock;
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
-use rand::Rng;
-use wasmer::{Instance, Store};
+use wasmer::{ImportObject, Instance};
+use wasmer_wasi::{WasiEnv, WasiError, WasiState};
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum ArgType {
     /// Signed 32 bit integer.
     I32,
@@ -44,10 +51,72 @@ impl From<ArgType> for wasmer::Type {
 }
 
 use crate::{
-    compile_wasm, module_store::ModuleStore,
-    server::routes::register_function::RegisterModulePayload,
+    module_store::{ModulePackage, ModuleStore},
+    runtime::{
+        signature::{check_args, SignatureError},
+        values::{format_value, funcref_name, parse_value},
+    },
 };
 
+/// Outcome of a call into the library. Anything but `Ok` leaves the out-parameters untouched
+/// and records a message for `last_error_message`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasmStatus {
+    Ok,
+    /// A pointer is null or a string isn't valid UTF-8 or base64.
+    InvalidArgument,
+    RuntimeNotFound,
+    ModuleNotFound,
+    FunctionNotFound,
+    /// The arguments don't match the function signature.
+    SignatureMismatch,
+    /// The module couldn't be compiled or linked.
+    RegistrationFailed,
+    /// Instantiating or running the function failed.
+    ExecutionFailed,
+    /// The library panicked, this is a bug.
+    Panic,
+}
+
+#[derive(Debug)]
+pub struct FfiError {
+    pub status: WasmStatus,
+    pub message: String,
+}
+
+impl FfiError {
+    fn new(status: WasmStatus, message: impl fmt::Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for FfiError {}
+
+impl From<SignatureError> for FfiError {
+    fn from(err: SignatureError) -> Self {
+        let status = match err {
+            SignatureError::FunctionNotFound(_) | SignatureError::NotAFunction(_) => {
+                WasmStatus::FunctionNotFound
+            }
+            _ => WasmStatus::SignatureMismatch,
+        };
+
+        FfiError::new(status, err)
+    }
+}
+
+/// Example modules bundled with the library. Registered modules are exported back from their
+/// own bytes, these are only a source of data to register.
 #[repr(C)]
 pub enum StaticModuleList {
     WasmDiv,
@@ -55,42 +124,156 @@ pub enum StaticModuleList {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct WasmArg {
     pub value: *const c_char,
     pub arg_type: ArgType,
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct WasmFunction {
     pub name: *const c_char,
-    pub args: [WasmArg; 2],
+    /// `args_len` arguments, may be null when there are none.
+    pub args: *const WasmArg,
+    pub args_len: usize,
+}
+
+impl WasmFunction {
+    /// # Safety
+    /// `args` must point to `args_len` arguments, or be null.
+    unsafe fn args(&self) -> &[WasmArg] {
+        match self.args.is_null() {
+            true => &[],
+            false => std::slice::from_raw_parts(self.args, self.args_len),
+        }
+    }
 }
 
-impl From<WasmFunction> for crate::runtime::execute_module::WasmFunction {
-    fn from(function_compat: WasmFunction) -> Self {
-        let name = unsafe { CStr::from_ptr(function_compat.name) }
-            .to_str()
-            .unwrap()
-            .to_owned();
+impl TryFrom<WasmFunction> for crate::runtime::execute_module::WasmFunction {
+    type Error = FfiError;
 
-        let args = function_compat
-            .args
+    fn try_from(function_compat: WasmFunction) -> Result<Self, FfiError> {
+        let name = unsafe { c_str(function_compat.name, "function name") }?.to_owned();
+
+        let args = unsafe { function_compat.args() }
+            .iter()
             .map(|arg| {
-                let value = unsafe { CStr::from_ptr(arg.value) }
-                    .to_str()
-                    .unwrap()
-                    .to_owned();
-
-                crate::runtime::execute_module::WasmArg {
-                    value,
-                    arg_type: arg.arg_type.into(),
-                }
+                let value = unsafe { c_str(arg.value, "argument value") }?.to_owned();
+
+                Ok(crate::runtime::execute_module::WasmArg {
+                    value: value.into(),
+                    arg_type: wasmer::Type::from(arg.arg_type).into(),
+                })
             })
-            .to_vec();
+            .collect::<Result<_, FfiError>>()?;
+
+        Ok(Self {
+            name,
+            args,
+            results: vec![],
+            values: Default::default(),
+        })
+    }
+}
+
+#[repr(C)]
+pub union WasmValueData {
+    pub i32: i32,
+    pub i64: i64,
+    pub f32: f32,
+    pub f64: f64,
+    /// Little-endian bytes.
+    pub v128: [u8; 16],
+    /// `ExternRef` host handles and `FuncRef` export names as text, null for null references.
+    /// Owned by the [`WasmResults`] it is part of.
+    pub reference: *mut c_char,
+}
+
+#[repr(C)]
+pub struct WasmValue {
+    pub value_type: ArgType,
+    pub data: WasmValueData,
+}
+
+/// Values returned by a function, empty for functions that return nothing. Must be released
+/// with `free_wasm_results`.
+#[repr(C)]
+pub struct WasmResults {
+    pub values: *mut WasmValue,
+    pub len: usize,
+}
+
+impl WasmValue {
+    fn new(value: &wasmer::Value, instance: &Instance) -> Self {
+        let text = |text: Option<String>| match text {
+            Some(text) => c_string(&text).into_raw(),
+            None => std::ptr::null_mut(),
+        };
 
-        Self { name, args }
+        let (value_type, data) = match value {
+            wasmer::Value::I32(value) => (ArgType::I32, WasmValueData { i32: *value }),
+            wasmer::Value::I64(value) => (ArgType::I64, WasmValueData { i64: *value }),
+            wasmer::Value::F32(value) => (ArgType::F32, WasmValueData { f32: *value }),
+            wasmer::Value::F64(value) => (ArgType::F64, WasmValueData { f64: *value }),
+            wasmer::Value::V128(value) => (
+                ArgType::V128,
+                WasmValueData {
+                    v128: value.to_le_bytes(),
+                },
+            ),
+            wasmer::Value::ExternRef(reference) => (
+                ArgType::ExternRef,
+                WasmValueData {
+                    reference: text((!reference.is_null()).then(|| format_value(value))),
+                },
+            ),
+            wasmer::Value::FuncRef(function) => (
+                ArgType::FuncRef,
+                WasmValueData {
+                    reference: text(funcref_name(function.as_ref(), instance)),
+                },
+            ),
+        };
+
+        Self { value_type, data }
+    }
+}
+
+impl WasmResults {
+    fn new(values: &[wasmer::Value], instance: &Instance) -> Self {
+        let values = values
+            .iter()
+            .map(|value| WasmValue::new(value, instance))
+            .collect::<Box<[_]>>();
+        let len = values.len();
+
+        Self {
+            values: Box::into_raw(values) as *mut WasmValue,
+            len,
+        }
+    }
+}
+
+/// Owned nul-terminated strings. Must be released with `free_wasm_strings`.
+#[repr(C)]
+pub struct WasmStrings {
+    pub values: *mut *mut c_char,
+    pub len: usize,
+}
+
+impl WasmStrings {
+    fn new(strings: Vec<String>) -> Self {
+        let values = strings
+            .into_iter()
+            .map(|string| c_string(&string).into_raw())
+            .collect::<Box<[_]>>();
+        let len = values.len();
+
+        Self {
+            values: Box::into_raw(values) as *mut *mut c_char,
+            len,
+        }
     }
 }
 
@@ -105,16 +288,6 @@ impl StaticModuleList {
         };
         base64::encode(data)
     }
-
-    fn data_from_str(name: &str) -> String {
-        let data = match name {
-            "div" => WASM_DIV,
-            "sum" => WASM_SUM,
-            _ => panic!("Unknown module name"),
-        };
-
-        base64::encode(data)
-    }
 }
 
 #[derive(Default)]
@@ -124,187 +297,494 @@ pub struct WasmRuntime {
 
 #[no_mangle]
 static SHARED_RUNTIMES: Lazy<ShardedLock<HashMap<u64, Mutex<WasmRuntime>>>> =
-    Lazy::new(|| ShardedLock::default());
-
-static STORE: Lazy<Store> = Lazy::new(|| Store::default());
-
-#[no_mangle]
-pub extern "C" fn initialize_runtime() -> u64 {
-    let mut rng = rand::thread_rng();
-    let rand: u64 = rng.gen();
+    Lazy::new(ShardedLock::default);
+
+/// Ids are never reused, 0 is left for errors of calls without a runtime.
+static NEXT_RUNTIME_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Message of the last failed call per runtime id. Calls that don't take a runtime, or take
+/// one that doesn't exist, use 0.
+static LAST_ERRORS: Lazy<Mutex<HashMap<u64, String>>> = Lazy::new(Mutex::default);
+
+/// Runs `f`, turning its error or panic into a status and recording the message under
+/// `runtime_id`. Panics must never unwind into the caller.
+fn guard(runtime_id: u64, f: impl FnOnce() -> Result<(), FfiError>) -> WasmStatus {
+    let err = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return WasmStatus::Ok,
+        Ok(Err(err)) => err,
+        Err(payload) => FfiError::new(WasmStatus::Panic, panic_message(payload.as_ref())),
+    };
 
-    SHARED_RUNTIMES
-        .write()
-        .expect("failed to get runtimes write lock")
-        .insert(rand, Mutex::default());
+    // Checked under the lock so `destroy_runtime` can't miss an entry it should remove.
+    let mut errors = LAST_ERRORS.lock();
+    let exists = SHARED_RUNTIMES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .contains_key(&runtime_id);
+    errors.insert(if exists { runtime_id } else { 0 }, err.message);
+    err.status
+}
 
-    rand
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    }
 }
 
-#[no_mangle]
-pub extern "C" fn get_static_module_data(module: StaticModuleList) -> *mut c_char {
-    let base_64 = module.data_base64().as_bytes().to_vec();
-    let base64_compat: CString = CString::new(base_64).unwrap();
+fn with_runtime<T>(
+    runtime_id: u64,
+    f: impl FnOnce(&mut WasmRuntime) -> Result<T, FfiError>,
+) -> Result<T, FfiError> {
+    let runtimes = SHARED_RUNTIMES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner);
+    let runtime = runtimes
+        .get(&runtime_id)
+        .ok_or_else(|| runtime_not_found(runtime_id))?;
 
-    base64_compat.into_raw()
+    let result = f(&mut runtime.lock());
+    result
 }
 
-#[no_mangle]
-pub extern "C" fn get_runtime_module_base64_data(
-    runtime_id: u64,
-    module_name: *const c_char,
-) -> *const c_char {
-    // println!("registering {}", runtime_id);
+/// Copies `string` for C, which would read it only up to the first nul, so nuls are dropped.
+fn c_string(string: &str) -> CString {
+    CString::new(string.replace('\0', "")).expect("nuls are removed")
+}
 
-    let module_name_str = unsafe { CStr::from_ptr(module_name) }
-        .to_str()
-        .expect("invalid string");
+/// # Safety
+/// `ptr` must be null or a valid nul-terminated string.
+unsafe fn c_str<'a>(ptr: *const c_char, what: &str) -> Result<&'a str, FfiError> {
+    if ptr.is_null() {
+        return Err(FfiError::new(
+            WasmStatus::InvalidArgument,
+            format!("{} is null", what),
+        ));
+    }
 
-    let runtime_lock = SHARED_RUNTIMES
-        .read()
-        .expect("failed to get runtime read lock");
+    CStr::from_ptr(ptr).to_str().map_err(|err| {
+        FfiError::new(
+            WasmStatus::InvalidArgument,
+            format!("{} is not UTF-8: {}", what, err),
+        )
+    })
+}
 
-    let runtime = runtime_lock
-        .get(&runtime_id)
-        .expect("failed to get runtime id");
+/// # Safety
+/// `out` must be null or valid for writes.
+unsafe fn write_out<T>(out: *mut T, value: T) -> Result<(), FfiError> {
+    if out.is_null() {
+        return Err(FfiError::new(
+            WasmStatus::InvalidArgument,
+            "out-parameter is null",
+        ));
+    }
 
-    let lock = runtime.lock();
+    out.write(value);
+    Ok(())
+}
 
-    let contains_module = lock.module_store.contains_key(module_name_str);
+/// # Safety
+/// `runtime_id` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn initialize_runtime(runtime_id: *mut u64) -> WasmStatus {
+    guard(0, || {
+        write_out(runtime_id, insert_runtime(WasmRuntime::default()))
+    })
+}
 
-    if !contains_module {
-        panic!("Missing module base64");
-    }
+/// Removes the runtime and every module registered with it.
+#[no_mangle]
+pub extern "C" fn destroy_runtime(runtime_id: u64) -> WasmStatus {
+    guard(runtime_id, || {
+        SHARED_RUNTIMES
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&runtime_id)
+            .ok_or_else(|| runtime_not_found(runtime_id))?;
+        LAST_ERRORS.lock().remove(&runtime_id);
+
+        Ok(())
+    })
+}
 
-    let base_64 = StaticModuleList::data_from_str(module_name_str);
-    // module.data_base64().as_bytes().to_vec();
-    let base64_compat: CString = CString::new(base_64).unwrap();
+/// Unregisters every module of the runtime, keeping its id.
+#[no_mangle]
+pub extern "C" fn reset_runtime(runtime_id: u64) -> WasmStatus {
+    guard(runtime_id, || {
+        with_runtime(runtime_id, |runtime| {
+            *runtime = WasmRuntime::default();
+            Ok(())
+        })?;
+        LAST_ERRORS.lock().remove(&runtime_id);
+
+        Ok(())
+    })
+}
 
-    base64_compat.into_raw()
+/// Creates a runtime with the modules of `runtime_id` registered. Compiled modules are shared,
+/// registering or removing modules afterwards only affects one of the two.
+///
+/// # Safety
+/// `clone_id` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn clone_runtime(runtime_id: u64, clone_id: *mut u64) -> WasmStatus {
+    guard(runtime_id, || {
+        let clone = with_runtime(runtime_id, |runtime| {
+            let module_store = runtime
+                .module_store
+                .fork()
+                .map_err(|error| FfiError::new(WasmStatus::RegistrationFailed, error))?;
+            Ok(WasmRuntime { module_store })
+        })?;
+
+        write_out(clone_id, insert_runtime(clone))
+    })
 }
 
+/// Writes the ids of up to `capacity` runtimes to `runtime_ids` and the number of runtimes to
+/// `count`, which may be larger than `capacity`. Ids are in creation order.
+///
+/// # Safety
+/// `runtime_ids` must be valid for `capacity` writes, or null when `capacity` is 0. `count`
+/// must be null or valid for writes.
 #[no_mangle]
-pub extern "C" fn register_module(
-    runtime_id: u64,
-    module_name: *const c_char,
-    module_data_base_64: *const c_char,
-) -> *const c_char {
-    // println!("registering {}", runtime_id);
+pub unsafe extern "C" fn list_runtimes(
+    runtime_ids: *mut u64,
+    capacity: usize,
+    count: *mut usize,
+) -> WasmStatus {
+    guard(0, || {
+        if runtime_ids.is_null() && capacity > 0 {
+            return Err(FfiError::new(
+                WasmStatus::InvalidArgument,
+                "runtime ids are null",
+            ));
+        }
 
-    let module_name_str = unsafe { CStr::from_ptr(module_name) }
-        .to_str()
-        .expect("invalid string");
+        let mut ids = SHARED_RUNTIMES
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        write_out(count, ids.len())?;
+        for (index, id) in ids.into_iter().take(capacity).enumerate() {
+            runtime_ids.add(index).write(id);
+        }
 
-    let module_name_data = unsafe { CStr::from_ptr(module_data_base_64) }
-        .to_str()
-        .expect("invalid data base 64");
+        Ok(())
+    })
+}
 
-    let module_payload = RegisterModulePayload {
-        data_base64: module_name_data.to_string(),
-        name: module_name_str.to_string(),
-        wasi: false,
-    };
+/// Writes the names of the modules registered with the runtime, sorted, to `names`.
+///
+/// # Safety
+/// `names` must be null or valid for writes. The names written to it must be released with
+/// [`free_wasm_strings`].
+#[no_mangle]
+pub unsafe extern "C" fn list_modules(runtime_id: u64, names: *mut WasmStrings) -> WasmStatus {
+    guard(runtime_id, || {
+        let mut module_names = with_runtime(runtime_id, |runtime| {
+            Ok(runtime
+                .module_store
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>())
+        })?;
+        module_names.sort_unstable();
+
+        write_out(names, WasmStrings::new(module_names))
+    })
+}
 
-    let data = base64::decode(module_payload.data_base64).expect("failed to decode module");
+/// # Safety
+/// `strings` must come from this library and not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_wasm_strings(strings: WasmStrings) {
+    if strings.values.is_null() {
+        return;
+    }
 
-    let module = compile_wasm(&STORE, &data).expect("failed to compile module wasm");
+    let values = Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        strings.values,
+        strings.len,
+    ));
+    for &value in values.iter() {
+        drop(CString::from_raw(value));
+    }
+}
 
-    let runtime_lock = SHARED_RUNTIMES
-        .read()
-        .expect("failed to get runtime read lock");
+fn insert_runtime(runtime: WasmRuntime) -> u64 {
+    let runtime_id = NEXT_RUNTIME_ID.fetch_add(1, Ordering::Relaxed);
 
-    let runtime = runtime_lock
-        .get(&runtime_id)
-        .expect("failed to get runtime id");
+    SHARED_RUNTIMES
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(runtime_id, Mutex::new(runtime));
 
-    let mut lock = runtime.lock();
+    runtime_id
+}
 
-    lock.module_store
-        .add(module_payload.name, module, module_payload.wasi)
-        .expect("failed to add module to store");
+/// # Safety
+/// `data` must be null or valid for writes. The string written to it must be released with
+/// [`free_ffi_string`].
+#[no_mangle]
+pub unsafe extern "C" fn get_static_module_data(
+    module: StaticModuleList,
+    data: *mut *mut c_char,
+) -> WasmStatus {
+    guard(0, || {
+        let base64_compat = CString::new(module.data_base64()).expect("base64 has no nul");
+
+        write_out(data, base64_compat.into_raw())
+    })
+}
 
-    module_name
+/// # Safety
+/// `module_name` must be a valid nul-terminated string, `data` must be null or valid for
+/// writes. The string written to it must be released with [`free_ffi_string`].
+#[no_mangle]
+pub unsafe extern "C" fn get_runtime_module_base64_data(
+    runtime_id: u64,
+    module_name: *const c_char,
+    data: *mut *mut c_char,
+) -> WasmStatus {
+    guard(runtime_id, || {
+        let module_name = c_str(module_name, "module name")?;
+
+        let base_64 = with_runtime(runtime_id, |runtime| {
+            let module = runtime
+                .module_store
+                .get(module_name)
+                .ok_or_else(|| module_not_found(module_name))?;
+
+            Ok(base64::encode(&module.data))
+        })?;
+        let base64_compat = CString::new(base_64).expect("base64 has no nul");
+
+        write_out(data, base64_compat.into_raw())
+    })
 }
 
+/// # Safety
+/// `module_name` and `module_data_base_64` must be valid nul-terminated strings.
 #[no_mangle]
-pub extern "C" fn free_ffi_string(data: *mut c_char) {
-    unsafe { CString::from_raw(data) };
+pub unsafe extern "C" fn register_module(
+    runtime_id: u64,
+    module_name: *const c_char,
+    module_data_base_64: *const c_char,
+) -> WasmStatus {
+    guard(runtime_id, || {
+        let module_name = c_str(module_name, "module name")?;
+        let data = base64::decode(c_str(module_data_base_64, "module data")?).map_err(|err| {
+            FfiError::new(
+                WasmStatus::InvalidArgument,
+                format!("module data is not base64: {}", err),
+            )
+        })?;
+
+        register(runtime_id, module_name, &data, false)
+    })
 }
 
+/// Registers the module in `data`, compiled or as WAT text, without a base64 round-trip.
+/// With `wasi`, every call gets a WASI environment of its own using the host's stdio.
+///
+/// # Safety
+/// `module_name` must be a valid nul-terminated string, `data` must point to `len` bytes.
 #[no_mangle]
-pub extern "C" fn is_module_registered(runtime_id: u64, module_name: *const c_char) -> bool {
-    // println!("executing {:#?}", function);
+pub unsafe extern "C" fn register_module_bytes(
+    runtime_id: u64,
+    module_name: *const c_char,
+    data: *const u8,
+    len: usize,
+    wasi: bool,
+) -> WasmStatus {
+    guard(runtime_id, || {
+        let module_name = c_str(module_name, "module name")?;
+        if data.is_null() {
+            return Err(FfiError::new(
+                WasmStatus::InvalidArgument,
+                "module data is null",
+            ));
+        }
 
-    let module_name = unsafe { CStr::from_ptr(module_name) }
-        .to_str()
-        .expect("invalid string");
-    let runtime_lock = SHARED_RUNTIMES
-        .read()
-        .expect("failed to get runtime read lock");
+        register(
+            runtime_id,
+            module_name,
+            std::slice::from_raw_parts(data, len),
+            wasi,
+        )
+    })
+}
 
-    let runtime = runtime_lock
-        .get(&runtime_id)
-        .expect("failed to get runtime id");
+fn register(runtime_id: u64, name: &str, data: &[u8], wasi: bool) -> Result<(), FfiError> {
+    with_runtime(runtime_id, |runtime| {
+        runtime
+            .module_store
+            .add(name, data, wasi)
+            .map_err(|err| FfiError::new(WasmStatus::RegistrationFailed, format!("{:#}", err)))
+    })?;
 
-    let lock = runtime.lock();
+    Ok(())
+}
 
-    lock.module_store.contains_key(module_name)
+/// # Safety
+/// `data` must be null or a string returned by this library that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_ffi_string(data: *mut c_char) {
+    if !data.is_null() {
+        drop(CString::from_raw(data));
+    }
 }
 
+/// # Safety
+/// `module_name` must be a valid nul-terminated string, `registered` must be null or valid for
+/// writes.
 #[no_mangle]
-pub extern "C" fn execute_module(
+pub unsafe extern "C" fn is_module_registered(
     runtime_id: u64,
     module_name: *const c_char,
-    function: WasmFunction,
-) -> i32 {
-    // println!("executing {:#?}", function);
-
-    let module_name = unsafe { CStr::from_ptr(module_name) }
-        .to_str()
-        .expect("invalid string");
+    registered: *mut bool,
+) -> WasmStatus {
+    guard(runtime_id, || {
+        let module_name = c_str(module_name, "module name")?;
+        let contains_module = with_runtime(runtime_id, |runtime| {
+            Ok(runtime.module_store.contains_key(module_name))
+        })?;
+
+        write_out(registered, contains_module)
+    })
+}
 
-    let func_name = unsafe { CStr::from_ptr(function.name) }
-        .to_str()
-        .expect("invalid string");
+/// # Safety
+/// `module_name`, `function.name` and every argument value must be valid nul-terminated strings,
+/// `function.args` must point to `function.args_len` arguments and `results` must be null or
+/// valid for writes. The results written to it must be released with [`free_wasm_results`].
+#[no_mangle]
+pub unsafe extern "C" fn execute_module(
+    runtime_id: u64,
+    module_name: *const c_char,
+    function: WasmFunction,
+    results: *mut WasmResults,
+) -> WasmStatus {
+    guard(runtime_id, || {
+        let module_name = c_str(module_name, "module name")?;
+        let request = function.try_into()?;
+
+        let module = with_runtime(runtime_id, |runtime| {
+            runtime
+                .module_store
+                .get(module_name)
+                .cloned()
+                .ok_or_else(|| module_not_found(module_name))
+        })?;
+
+        check_args(&module.module, &request)?;
+
+        let failed = |err: &dyn fmt::Display| FfiError::new(WasmStatus::ExecutionFailed, err);
+        let linked = match module.wasi {
+            true => {
+                wasi_imports(&module, module_name).and_then(|imports| module.instantiate(imports))
+            }
+            false => module.instantiate(&module.imports),
+        }
+        .map_err(|err| failed(&err))?;
+        let instance = &linked.instance;
+        let wasm_function = instance
+            .exports
+            .get_function(&request.name)
+            .map_err(|err| failed(&err))?;
+
+        let args = function
+            .args()
+            .iter()
+            .map(|arg| parse_arg(arg, instance))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let fn_result = match wasm_function.call(&args) {
+            Ok(values) => values,
+            // WASI programs calling `exit(0)` end with this error.
+            Err(err) => match err.downcast::<WasiError>() {
+                Ok(WasiError::Exit(0)) => Box::new([]),
+                Ok(err) => return Err(failed(&err)),
+                Err(err) => return Err(failed(&err)),
+            },
+        };
 
-    let runtime_lock = SHARED_RUNTIMES
-        .read()
-        .expect("failed to get runtime read lock");
+        write_out(results, WasmResults::new(&fn_result, instance))
+    })
+}
 
-    let runtime = runtime_lock
-        .get(&runtime_id)
-        .expect("failed to get runtime id");
+/// A WASI environment for a single call, with the module's default args, env and mounts and
+/// the host process's stdio.
+fn wasi_imports(module: &ModulePackage, program: &str) -> anyhow::Result<ImportObject> {
+    let mut builder = WasiState::new(program);
+    builder.args(&module.config.args).envs(&module.config.env);
+    module.mounts.preopen(&mut builder)?;
 
-    let lock = runtime.lock();
+    Ok(WasiEnv::new(builder.build()?).import_object(&module.module)?)
+}
 
-    let module = lock.module_store.get(&module_name).expect("missing module");
+/// # Safety
+/// `results` must come from [`execute_module`] and not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_wasm_results(results: WasmResults) {
+    if results.values.is_null() {
+        return;
+    }
 
-    let instance = Instance::new(&module.module, &module.imports).unwrap();
+    let values = Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        results.values,
+        results.len,
+    ));
 
-    let wasm_function = instance.exports.get_function(func_name).unwrap();
+    for value in values.iter() {
+        if matches!(value.value_type, ArgType::ExternRef | ArgType::FuncRef)
+            && !value.data.reference.is_null()
+        {
+            drop(CString::from_raw(value.data.reference));
+        }
+    }
+}
 
-    let args = [
-        parse_arg(&function.args[0]).unwrap(),
-        parse_arg(&function.args[1]).unwrap(),
-    ];
+/// Message of the last call on `runtime_id` that didn't return `Ok`, or null if there is none.
+/// Calls that don't take a runtime, or take one that doesn't exist, record their errors under
+/// 0. The message must be released with [`free_ffi_string`].
+#[no_mangle]
+pub extern "C" fn last_error_message(runtime_id: u64) -> *mut c_char {
+    panic::catch_unwind(|| {
+        let message = LAST_ERRORS.lock().get(&runtime_id).cloned()?;
 
-    let fn_result = wasm_function.call(&args).unwrap();
+        Some(c_string(&message))
+    })
+    .ok()
+    .flatten()
+    .map_or(std::ptr::null_mut(), CString::into_raw)
+}
 
-    let result = fn_result[0].i32().unwrap();
+fn runtime_not_found(runtime_id: u64) -> FfiError {
+    FfiError::new(
+        WasmStatus::RuntimeNotFound,
+        format!("runtime not found: {}", runtime_id),
+    )
+}
 
-    result
+fn module_not_found(name: &str) -> FfiError {
+    FfiError::new(
+        WasmStatus::ModuleNotFound,
+        format!("module not found: {}", name),
+    )
 }
 
-fn parse_arg(arg: &WasmArg) -> anyhow::Result<wasmer::Value> {
-    let value = unsafe { CStr::from_ptr(arg.value).to_str()? };
+fn parse_arg(arg: &WasmArg, instance: &Instance) -> Result<wasmer::Value, FfiError> {
+    let value = unsafe { c_str(arg.value, "argument value") }?;
 
-    Ok(match arg.arg_type {
-        ArgType::I32 => wasmer::Value::I32(value.parse()?),
-        ArgType::I64 => wasmer::Value::I64(value.parse()?),
-        ArgType::F32 => wasmer::Value::F32(value.parse()?),
-        ArgType::F64 => wasmer::Value::F64(value.parse()?),
-        ArgType::V128 => todo!(),
-        ArgType::ExternRef => todo!(),
-        ArgType::FuncRef => todo!(),
-    })
+    parse_value(arg.arg_type.into(), value, instance)
+        .map_err(|err| FfiError::new(WasmStatus::InvalidArgument, format!("{:#}", err)))
 }
@@ -0,0 +1,248 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::module_store::ModuleConfig;
+
+/// Metadata persisted for every module version, next to its raw wasm.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionRecord {
+    pub version: u64,
+    pub hash: String,
+    pub wasi: bool,
+    #[serde(default)]
+    pub config: ModuleConfig,
+    pub created_at: NaiveDateTime,
+    /// Store-wide registration order, used to replay registrations at startup.
+    pub sequence: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StoredModule {
+    pub name: String,
+    pub versions: Vec<(VersionRecord, Vec<u8>)>,
+    pub aliases: BTreeMap<String, u64>,
+}
+
+/// Durable backend for the module store. Writes happen before the in-memory store is
+/// updated, so a failing backend rejects the change.
+pub trait ModuleStorage: Send + Sync {
+    /// Replaces any version saved under the same number.
+    fn save_version(&self, name: &str, record: &VersionRecord, data: &[u8]) -> anyhow::Result<()>;
+
+    fn save_aliases(&self, name: &str, aliases: &BTreeMap<String, u64>) -> anyhow::Result<()>;
+
+    fn remove_module(&self, name: &str) -> anyhow::Result<()>;
+
+    fn load(&self) -> anyhow::Result<Vec<StoredModule>>;
+}
+
+/// Keeps everything in memory. Clones share the same contents, which lets tests reopen a
+/// module store from the storage of a previous one.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    modules: Arc<Mutex<BTreeMap<String, StoredModule>>>,
+}
+
+impl MemoryStorage {
+    /// A storage holding its own copy of `modules`.
+    pub fn with_modules(modules: Vec<StoredModule>) -> Self {
+        let modules = modules
+            .into_iter()
+            .map(|module| (module.name.clone(), module))
+            .collect();
+        Self {
+            modules: Arc::new(Mutex::new(modules)),
+        }
+    }
+}
+
+impl ModuleStorage for MemoryStorage {
+    fn save_version(&self, name: &str, record: &VersionRecord, data: &[u8]) -> anyhow::Result<()> {
+        let mut modules = self.modules.lock();
+        let module = modules
+            .entry(name.to_owned())
+            .or_insert_with(|| StoredModule {
+                name: name.to_owned(),
+                ..Default::default()
+            });
+        module
+            .versions
+            .retain(|(saved, _)| saved.version != record.version);
+        module.versions.push((record.clone(), data.to_vec()));
+        Ok(())
+    }
+
+    fn save_aliases(&self, name: &str, aliases: &BTreeMap<String, u64>) -> anyhow::Result<()> {
+        if let Some(module) = self.modules.lock().get_mut(name) {
+            module.aliases = aliases.clone();
+        }
+        Ok(())
+    }
+
+    fn remove_module(&self, name: &str) -> anyhow::Result<()> {
+        self.modules.lock().remove(name);
+        Ok(())
+    }
+
+    fn load(&self) -> anyhow::Result<Vec<StoredModule>> {
+        Ok(self.modules.lock().values().cloned().collect())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    versions: Vec<VersionRecord>,
+    aliases: BTreeMap<String, u64>,
+}
+
+const MANIFEST: &str = "module.json";
+
+/// Stores each module in its own directory: a `module.json` manifest plus one
+/// `<version>.wasm` file per version.
+#[derive(Debug, Clone)]
+pub struct DirectoryStorage {
+    root: PathBuf,
+}
+
+impl DirectoryStorage {
+    pub fn open(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)
+            .with_context(|| format!("failed to create {}", root.display()))?;
+        Ok(Self { root })
+    }
+
+    fn module_dir(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    fn read_manifest(dir: &Path) -> anyhow::Result<Manifest> {
+        let path = dir.join(MANIFEST);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+
+        let data = fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_slice(&data).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    fn write_manifest(dir: &Path, manifest: &Manifest) -> anyhow::Result<()> {
+        write_atomic(&dir.join(MANIFEST), &serde_json::to_vec_pretty(manifest)?)
+    }
+}
+
+/// Writes to a temporary file first so a crash never leaves a truncated file behind.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data).with_context(|| format!("failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+impl ModuleStorage for DirectoryStorage {
+    fn save_version(&self, name: &str, record: &VersionRecord, data: &[u8]) -> anyhow::Result<()> {
+        let dir = self.module_dir(name);
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+        write_atomic(&dir.join(format!("{}.wasm", record.version)), data)?;
+
+        let mut manifest = Self::read_manifest(&dir)?;
+        manifest.versions.retain(|v| v.version != record.version);
+        manifest.versions.push(record.clone());
+        Self::write_manifest(&dir, &manifest)
+    }
+
+    fn save_aliases(&self, name: &str, aliases: &BTreeMap<String, u64>) -> anyhow::Result<()> {
+        let dir = self.module_dir(name);
+        let mut manifest = Self::read_manifest(&dir)?;
+        manifest.aliases = aliases.clone();
+        Self::write_manifest(&dir, &manifest)
+    }
+
+    fn remove_module(&self, name: &str) -> anyhow::Result<()> {
+        let dir = self.module_dir(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("failed to remove {}", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> anyhow::Result<Vec<StoredModule>> {
+        let mut modules = Vec::new();
+
+        for dir in fs::read_dir(&self.root)? {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+
+            let name = dir.file_name().to_string_lossy().into_owned();
+            let manifest = Self::read_manifest(&dir.path())?;
+
+            let versions = manifest
+                .versions
+                .into_iter()
+                .map(|record| {
+                    let path = dir.path().join(format!("{}.wasm", record.version));
+                    let data = fs::read(&path)
+                        .with_context(|| format!("failed to read {}", path.display()))?;
+                    Ok((record, data))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            modules.push(StoredModule {
+                name,
+                versions,
+                aliases: manifest.aliases,
+            });
+        }
+
+        Ok(modules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{DirectoryStorage, ModuleStorage, VersionRecord};
+
+    #[test]
+    fn test_directory_storage_roundtrip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = DirectoryStorage::open(dir.path())?;
+
+        let record = VersionRecord {
+            version: 1,
+            hash: "hash".into(),
+            wasi: true,
+            config: Default::default(),
+            created_at: Utc::now().naive_utc(),
+            sequence: 0,
+        };
+        storage.save_version("sum", &record, b"wasm")?;
+        storage.save_aliases("sum", &[("latest".to_owned(), 1)].into_iter().collect())?;
+
+        let modules = DirectoryStorage::open(dir.path())?.load()?;
+        assert_eq!(modules.len(), 1);
+        assert_eq!(modules[0].name, "sum");
+        assert_eq!(modules[0].versions, vec![(record, b"wasm".to_vec())]);
+        assert_eq!(modules[0].aliases["latest"], 1);
+
+        storage.remove_module("sum")?;
+        assert!(storage.load()?.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,6 +1,18 @@
 use wasmfaas::{
-    runtime::execute_module::ExecuteModuleRequest,
-    server::routes::register_function::RegisterModulePayload,
+    module_store::ModuleVersionInfo,
+    runtime::{
+        execute_module::{ExecuteModuleRequest, ExecuteModuleResponse, WasmType},
+        output::{OutputChunk, OutputStream},
+        signature::{ExportInfo, ExternInfo},
+    },
+    server::{
+        error::ErrorBody,
+        routes::{
+            execute_function::StreamEvent,
+            modules::{ModuleInfo, ModuleSummary},
+            register_function::RegisterModulePayload,
+        },
+    },
 };
 
 #[test]
@@ -27,6 +39,7 @@ async fn register_function(name: &str, wasm: Vec<u8>, wasi: bool) -> reqwest::Re
         data_base64: base_64,
         name: name.to_owned(),
         wasi,
+        config: Default::default(),
     };
 
     let request = client
@@ -75,3 +88,271 @@ async fn execute_function(request: ExecuteModuleRequest) -> reqwest::Response {
 
     client.execute(request).await.unwrap()
 }
+
+#[test]
+// don't forget to start the runtime before running those tests
+fn module_resource_test() {
+    let tokio_rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let function_data = std::fs::read("../binaries/compiled/sum.wasm").unwrap();
+    tokio_rt.block_on(async move {
+        register_function("sum_resource", function_data, false)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let client = reqwest::Client::new();
+
+        let modules: Vec<ModuleSummary> = client
+            .get("http://127.0.0.1:3000/modules")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(modules.iter().any(|m| m.name == "sum_resource"));
+
+        let module: ModuleInfo = client
+            .get("http://127.0.0.1:3000/modules/sum_resource")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(module.exports.contains(&ExportInfo {
+            name: "sum".to_owned(),
+            ty: ExternInfo::Function {
+                params: vec![WasmType::I32, WasmType::I32],
+                results: vec![WasmType::I32],
+            },
+        }));
+
+        let response = client
+            .post("http://127.0.0.1:3000/modules/sum_resource/functions/sum/invoke")
+            .json(&serde_json::json!({
+                "args": [{ "value": "2", "argType": "I64" }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let error: ErrorBody = response.json().await.unwrap();
+        assert_eq!(error.code.as_deref(), Some("signature_mismatch"));
+
+        let response: ExecuteModuleResponse = client
+            .post("http://127.0.0.1:3000/modules/sum_resource/functions/sum/invoke")
+            .json(&serde_json::json!({
+                "args": [
+                    { "value": 2, "argType": "I32" },
+                    { "value": "3", "argType": "I32" }
+                ]
+            }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response.results[0].result, 5);
+        assert!(response.fuel_consumed > 0);
+        assert!(!response.warm_start);
+
+        let called: ExecuteModuleResponse = client
+            .post("http://127.0.0.1:3000/modules/sum_resource/functions/sum/call")
+            .json(&serde_json::json!([20, 22]))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(called.results[0].result, 42);
+        assert!(called.fuel_consumed > 0);
+        assert!(!called.warm_start);
+
+        let response = client
+            .post("http://127.0.0.1:3000/modules/sum_resource/functions/sum/invoke")
+            .json(&serde_json::json!({
+                "args": [
+                    { "value": "2", "argType": "I32" },
+                    { "value": "3", "argType": "I32" }
+                ],
+                "fuel": 1
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        let error: ErrorBody = response.json().await.unwrap();
+        assert_eq!(error.code.as_deref(), Some("out_of_fuel"));
+
+        client
+            .delete("http://127.0.0.1:3000/modules/sum_resource")
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        let response = client
+            .get("http://127.0.0.1:3000/modules/sum_resource")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        let error: ErrorBody = response.json().await.unwrap();
+        assert_eq!(error.status, 404);
+    });
+}
+
+#[test]
+// don't forget to start the runtime before running those tests
+fn module_versions_test() {
+    let tokio_rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let sum_data = std::fs::read("../binaries/compiled/sum.wasm").unwrap();
+    let div_data = std::fs::read("../binaries/compiled/div.wasm").unwrap();
+    tokio_rt.block_on(async move {
+        let first: ModuleVersionInfo = register_function("versioned", sum_data, false)
+            .await
+            .json()
+            .await
+            .unwrap();
+        let second: ModuleVersionInfo = register_function("versioned", div_data, false)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(second.version, first.version + 1);
+
+        let client = reqwest::Client::new();
+        client
+            .put("http://127.0.0.1:3000/modules/versioned/aliases/stable")
+            .json(&serde_json::json!({ "version": first.version }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        let stable: ModuleInfo = client
+            .get("http://127.0.0.1:3000/modules/versioned:stable")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(stable.version, first.version);
+        assert!(stable.exports.iter().any(|export| export.name == "sum"));
+
+        let pinned = format!("versioned@{}", second.version);
+        let latest: ModuleInfo = client
+            .get(format!("http://127.0.0.1:3000/modules/{}", pinned))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(latest.exports.iter().any(|export| export.name == "div"));
+    });
+}
+
+#[test]
+// don't forget to start the runtime before running those tests
+fn stream_output_test() {
+    let tokio_rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let greet = br#"(module
+        (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 16) "hello\n")
+        (func (export "greet")
+            (i32.store (i32.const 0) (i32.const 16))
+            (i32.store (i32.const 4) (i32.const 6))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+
+    tokio_rt.block_on(async move {
+        register_function("greet_stream", greet.to_vec(), true)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let client = reqwest::Client::new();
+        let response: ExecuteModuleResponse = client
+            .post("http://127.0.0.1:3000/modules/greet_stream/functions/greet/invoke")
+            .json(&serde_json::json!({}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response.stdout.as_deref(), Some("hello\n"));
+
+        let body = client
+            .post("http://127.0.0.1:3000/modules/greet_stream/functions/greet/stream")
+            .json(&serde_json::json!({}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let events = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<StreamEvent>>();
+
+        assert!(matches!(
+            &events[0],
+            StreamEvent::Output(OutputChunk { stream: OutputStream::Stdout, data }) if data == "hello\n"
+        ));
+        assert!(matches!(events.last(), Some(StreamEvent::Result(_))));
+    });
+}
+
+#[test]
+fn run_command_test() {
+    let tokio_rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let function_data = std::fs::read("../binaries/rust/bin/hello_world.wasm").unwrap();
+
+    tokio_rt.block_on(async move {
+        register_function("hello_world", function_data, true)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let response: ExecuteModuleResponse = reqwest::Client::new()
+            .post("http://127.0.0.1:3000/modules/hello_world/run")
+            .json(&serde_json::json!({ "argv": ["--verbose"] }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(response.exit_code, Some(0));
+        assert_eq!(response.stdout.as_deref(), Some("Hello world\n"));
+    });
+}